@@ -1,12 +1,20 @@
 // Copyright (c) 2025 SharPi Contributors
 // MIT License
 
-use sharpi::clients::openai;
+use sharpi::clients::{self, ClientError};
 use sharpi::config;
+use sharpi::core::export::{self, ExportFormat};
+use sharpi::core::history::{Conversation, ConversationSettings, ListOptions, Role};
+use sharpi::core::retention::{self, ExpireAction, RetentionPolicy};
+use sharpi::core::store::HistoryStore;
+use sharpi::core::usage;
 use anyhow::{anyhow, Result};
 use std::env;
+use std::io::{self, Write};
 
 fn main() -> Result<()> {
+    init_logging();
+
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
@@ -22,10 +30,10 @@ fn main() -> Result<()> {
                 },
                 Some("help") => {
                     println!("SharPi Init - Configuration Management");
-                    println!("");
+                    println!();
                     println!("USAGE:");
                     println!("  spi init [OPTIONS]");
-                    println!("");
+                    println!();
                     println!("OPTIONS:");
                     println!("  --force                   Reinitialize, overwriting existing configuration");
                     println!("  help                      Show this help message");
@@ -48,9 +56,25 @@ fn main() -> Result<()> {
             match subcommand.as_deref() {
                 // List conversations
                 Some("ls") | Some("list") => {
+                    let mut options = ListOptions::default();
+                    for i in 3..args.len() {
+                        let value = args.get(i + 1);
+                        match (args[i].as_str(), value) {
+                            ("--sort", Some(value)) => options.sort = value.parse()?,
+                            ("-n", Some(value)) | ("--limit", Some(value)) => {
+                                options.limit = Some(value.parse().map_err(|_| anyhow!("Invalid limit: {}", value))?);
+                            },
+                            ("--since", Some(value)) => options.since = Some(parse_date(value)?),
+                            ("--until", Some(value)) => options.until = Some(parse_date(value)?),
+                            ("--tag", Some(value)) => options.tag = Some(value.clone()),
+                            ("-a", _) | ("--all", _) => options.include_archived = true,
+                            _ => {},
+                        }
+                    }
+
                     match sharpi::core::history::load_history() {
                         Ok(history) => {
-                            match history.list_conversations() {
+                            match history.list_conversations(&options) {
                                 Ok(conversations) => {
                                     if conversations.is_empty() {
                                         println!("No conversations found.");
@@ -66,11 +90,24 @@ fn main() -> Result<()> {
                                             let message_count = metadata.message_count;
                                             let last_updated = metadata.updated_at.format("%Y-%m-%d %H:%M");
 
-                                            println!("{}{} - {} ({}messages, updated: {})",
+                                            let mut labels = String::new();
+                                            if metadata.pinned {
+                                                labels.push_str(" [pinned]");
+                                            }
+                                            if metadata.archived {
+                                                labels.push_str(" [archived]");
+                                            }
+                                            for tag in &metadata.tags {
+                                                labels.push_str(&format!(" #{}", tag));
+                                            }
+
+                                            println!("{}{} - {}{} ({} messages, {} tokens, updated: {})",
                                                 active_marker,
                                                 id,
                                                 metadata.title,
+                                                labels,
                                                 message_count,
+                                                metadata.usage.total_tokens,
                                                 last_updated
                                             );
                                         }
@@ -92,25 +129,34 @@ fn main() -> Result<()> {
 
                 // Create new conversation
                 Some("new") => {
-                    let mut title_index = None;
+                    let mut title = None;
+                    let mut system_prompt = None;
+                    let mut settings = ConversationSettings::default();
+
+                    let usage = "Usage: spi chat new -t \"Conversation Title\" [--system \"prompt\"] [--model <model>] [--temperature <t>] [--max-tokens <n>]";
 
-                    // Check for -t flag
+                    // Check for -t and the persona flags
                     for i in 3..args.len() {
-                        if args[i] == "-t" && i + 1 < args.len() {
-                            title_index = Some(i + 1);
-                            break;
+                        let value = args.get(i + 1);
+                        match (args[i].as_str(), value) {
+                            ("-t", Some(value)) => title = Some(value.clone()),
+                            ("--system", Some(value)) => system_prompt = Some(value.clone()),
+                            ("--model", Some(value)) => settings.model = Some(value.clone()),
+                            ("--temperature", Some(value)) => {
+                                settings.temperature = Some(value.parse().map_err(|_| anyhow!("Invalid temperature: {}", value))?);
+                            },
+                            ("--max-tokens", Some(value)) => {
+                                settings.max_tokens = Some(value.parse().map_err(|_| anyhow!("Invalid max tokens: {}", value))?);
+                            },
+                            _ => {},
                         }
                     }
 
-                    if title_index.is_none() {
-                        return Err(anyhow!("Usage: spi chat new -t \"Conversation Title\""));
-                    }
-
-                    let title = args[title_index.unwrap()].clone();
+                    let title = title.ok_or_else(|| anyhow!(usage))?;
 
                     match sharpi::core::history::load_history() {
                         Ok(mut history) => {
-                            match history.create_conversation(title) {
+                            match history.create_conversation_with_settings(title, system_prompt, settings) {
                                 Ok((id, conversation)) => {
                                     println!("Created new conversation: {} (ID: {})", conversation.title, id);
                                     sharpi::core::history::save_history(&history)?;
@@ -141,7 +187,7 @@ fn main() -> Result<()> {
                         Ok(history) => {
                             // Use active conversation if no ID provided
                             let conversation_id = match conversation_id_option {
-                                Some(id) => id,
+                                Some(reference) => history.resolve(&reference)?,
                                 None => match &history.active_conversation_id {
                                     Some(id) => id.clone(),
                                     None => return Err(anyhow!("No active conversation. Use: spi chat show <conversation_id>"))
@@ -153,17 +199,81 @@ fn main() -> Result<()> {
                                     println!("Conversation: {} (ID: {})", conversation.title, conversation_id);
                                     println!("Created: {}", conversation.created_at.format("%Y-%m-%d %H:%M"));
                                     println!("Messages: {}", conversation.messages.len());
+                                    if !conversation.tags.is_empty() {
+                                        println!("Tags: {}", conversation.tags.join(", "));
+                                    }
+                                    if conversation.pinned || conversation.archived {
+                                        let flags: Vec<&str> = [(conversation.pinned, "pinned"), (conversation.archived, "archived")]
+                                            .into_iter()
+                                            .filter_map(|(set, flag)| set.then_some(flag))
+                                            .collect();
+                                        println!("Status: {}", flags.join(", "));
+                                    }
+                                    println!("Tokens: {} (prompt: {}, completion: {})",
+                                        conversation.usage.total_tokens,
+                                        conversation.usage.prompt_tokens,
+                                        conversation.usage.completion_tokens
+                                    );
+                                    if let Some(origin) = &conversation.forked_from {
+                                        let lineage = history.lineage(&conversation_id).unwrap_or_default();
+                                        let mut at = origin.message_index;
+                                        println!("Forked from:");
+                                        for (parent_id, parent) in &lineage {
+                                            println!("  {} (ID: {}) at message {}", parent.title, parent_id, at);
+                                            at = parent.forked_from.as_ref().map_or(0, |origin| origin.message_index);
+                                        }
+                                        if lineage.is_empty() {
+                                            println!("  {} (deleted) at message {}", origin.conversation_id, at);
+                                        }
+                                    }
+                                    if let Some(system_prompt) = &conversation.system_prompt {
+                                        println!("System prompt: {}", system_prompt);
+                                    }
+                                    let settings = &conversation.settings;
+                                    if let Some(model) = &settings.model {
+                                        println!("Model: {}", model);
+                                    }
+                                    if let Some(temperature) = settings.temperature {
+                                        println!("Temperature: {}", temperature);
+                                    }
+                                    if let Some(max_tokens) = settings.max_tokens {
+                                        println!("Max tokens: {}", max_tokens);
+                                    }
+                                    if let Some(summary) = &conversation.summary {
+                                        println!("Summary (covers the first {} messages): {}", summary.covers, summary.content);
+                                    }
                                     println!();
 
                                     if conversation.messages.is_empty() {
                                         println!("No messages in this conversation.");
                                     } else {
                                         for (i, message) in conversation.messages.iter().enumerate() {
-                                            let role = if message.role == "user" { "You" } else { "AI" };
+                                            let role = match message.role {
+                                                Role::User => "You",
+                                                Role::Tool => "Tool",
+                                                Role::System => "System",
+                                                Role::Assistant => "AI",
+                                            };
                                             let timestamp = message.timestamp.format("%Y-%m-%d %H:%M");
-                                            println!("[{}] {}: {}", timestamp, role, message.content);
+                                            let marker = if message.interrupted { " [interrupted]" } else { "" };
+                                            match &message.tool_call_id {
+                                                Some(call_id) => println!("#{} [{}] {} [{}]: {}", i, timestamp, role, call_id, message.content),
+                                                None => println!("#{} [{}] {}: {}{}", i, timestamp, role, message.content, marker),
+                                            }
+                                            if let Some(usage) = &message.usage {
+                                                println!("    ({} tokens: {} prompt, {} completion)",
+                                                    usage.total_tokens, usage.prompt_tokens, usage.completion_tokens);
+                                            }
+                                            for call in &message.tool_calls {
+                                                println!("    -> tool call [{}] {}({})", call.id, call.name, call.arguments);
+                                            }
+                                            match message.alternatives.len() {
+                                                0 => {},
+                                                1 => println!("    (replaces 1 earlier version)"),
+                                                n => println!("    (replaces {} earlier versions)", n),
+                                            }
 
-                                            if i < conversation.messages.len() - 1 && message.role == "assistant" {
+                                            if i < conversation.messages.len() - 1 && message.role == Role::Assistant {
                                                 println!();
                                             }
                                         }
@@ -188,17 +298,25 @@ fn main() -> Result<()> {
                 Some("send") => {
                     let mut conversation_id = None;
                     let mut message_index = None;
+                    let mut client_name = None;
+                    let mut stream = true;
 
                     // Check if the third argument is an ID (not starting with a dash)
                     if args.len() > 3 && !args[3].starts_with('-') {
                         conversation_id = Some(args[3].clone());
                     }
 
-                    // Process all arguments for -m flag
+                    // Process all arguments for -m and -c flags
                     for i in 3..args.len() {
                         if args[i] == "-m" && i + 1 < args.len() {
                             message_index = Some(i + 1);
                         }
+                        if args[i] == "-c" && i + 1 < args.len() {
+                            client_name = Some(args[i + 1].clone());
+                        }
+                        if args[i] == "--no-stream" {
+                            stream = false;
+                        }
                     }
 
                     if message_index.is_none() {
@@ -210,22 +328,517 @@ fn main() -> Result<()> {
                     println!("SharPi - AI Coding Assistant");
                     println!("Sending request to AI API with conversation history...");
 
-                    match openai::call_openai_with_history(&message, conversation_id.as_deref(), None) {
+                    let result = if stream {
+                        println!("\nResponse from AI API:");
+                        let result = clients::call_with_history_streaming(
+                            &message,
+                            conversation_id.as_deref(),
+                            client_name.as_deref(),
+                            &mut |delta| {
+                                print!("{}", delta);
+                                let _ = io::stdout().flush();
+                            },
+                        );
+                        println!();
+                        result
+                    } else {
+                        clients::call_with_history(&message, conversation_id.as_deref(), client_name.as_deref())
+                            .inspect(|response| {
+                                println!("\nResponse from AI API:");
+                                println!("{}", response.content);
+                            })
+                    };
+
+                    match result {
                         Ok(response) => {
+                            print_tool_calls(&response);
+                            Ok(())
+                        },
+                        Err(err) => {
+                            print_client_error(&err);
+                            Err(err)
+                        }
+                    }
+                },
+
+                // Regenerate the reply to the last user message
+                Some("retry") => {
+                    let mut conversation_id = None;
+                    let mut client_name = None;
+                    let mut stream = true;
+
+                    if args.len() > 3 && !args[3].starts_with('-') {
+                        conversation_id = Some(args[3].clone());
+                    }
+                    for i in 3..args.len() {
+                        if args[i] == "-c" && i + 1 < args.len() {
+                            client_name = Some(args[i + 1].clone());
+                        }
+                        if args[i] == "--no-stream" {
+                            stream = false;
+                        }
+                    }
+
+                    println!("Regenerating the last reply...");
+
+                    let result = if stream {
+                        println!("\nResponse from AI API:");
+                        let result = clients::retry_last_turn(
+                            conversation_id.as_deref(),
+                            client_name.as_deref(),
+                            Some(&mut |delta: &str| {
+                                print!("{}", delta);
+                                let _ = io::stdout().flush();
+                            }),
+                        );
+                        println!();
+                        result
+                    } else {
+                        clients::retry_last_turn(conversation_id.as_deref(), client_name.as_deref(), None)
+                            .inspect(|response| {
+                                println!("\nResponse from AI API:");
+                                println!("{}", response.content);
+                            })
+                    };
+
+                    match result {
+                        Ok(response) => {
+                            print_tool_calls(&response);
+                            Ok(())
+                        },
+                        Err(err) => {
+                            print_client_error(&err);
+                            Err(err)
+                        }
+                    }
+                },
+
+                // Rewrite a message, dropping everything after it
+                Some("edit") => {
+                    let usage = "Usage: spi chat edit [<conversation_id>] <message_index> -m \"new text\"";
+
+                    let positional: Vec<&String> = args[3..]
+                        .iter()
+                        .take_while(|arg| !arg.starts_with('-'))
+                        .collect();
+                    let (conversation_id, index) = match positional.as_slice() {
+                        [index] => (None, index),
+                        [id, index] => (Some(id.to_string()), index),
+                        _ => return Err(anyhow!(usage)),
+                    };
+                    let index: usize = index.parse().map_err(|_| anyhow!("Invalid message index: {}", index))?;
+
+                    let mut content = None;
+                    for i in 3..args.len() {
+                        if args[i] == "-m" && i + 1 < args.len() {
+                            content = Some(args[i + 1].clone());
+                        }
+                    }
+                    let content = content.ok_or_else(|| anyhow!(usage))?;
+
+                    let history = sharpi::core::history::load_history()?;
+                    let conversation_id = match conversation_id {
+                        Some(reference) => history.resolve(&reference)?,
+                        None => match history.active_conversation_id.clone() {
+                            Some(id) => id,
+                            None => return Err(anyhow!("No active conversation. Use: spi chat edit <conversation_id> <index> -m \"text\"")),
+                        },
+                    };
+
+                    let _lock = history.lock_conversation(&conversation_id)?;
+                    let mut conversation = history.get_conversation(&conversation_id)?;
+                    let removed = conversation.messages.len().saturating_sub(index + 1);
+                    conversation.edit_message(index, content)?;
+                    history.save_conversation(&conversation_id, &conversation)?;
+
+                    println!("Edited message {}; removed {} later messages (kept as an alternative).", index, removed);
+                    if conversation.messages[index].role == Role::User {
+                        println!("Run 'spi chat retry' to get a reply to the edited message.");
+                    }
+                    Ok(())
+                },
+
+                // Return the output of a tool call to the model
+                Some("tool-result") => {
+                    let mut output_index = None;
+
+                    if args.len() <= 3 || args[3].starts_with('-') {
+                        return Err(anyhow!("Usage: spi chat tool-result <call_id> -m \"output\""));
+                    }
+                    let call_id = args[3].clone();
+
+                    for i in 4..args.len() {
+                        if args[i] == "-m" && i + 1 < args.len() {
+                            output_index = Some(i + 1);
+                        }
+                    }
+
+                    let output = match output_index {
+                        Some(index) => args[index].clone(),
+                        None => return Err(anyhow!("Usage: spi chat tool-result <call_id> -m \"output\"")),
+                    };
+
+                    match clients::submit_tool_result(&call_id, &output, None, None) {
+                        Ok(Some(response)) => {
                             println!("\nResponse from AI API:");
-                            println!("{}", response);
+                            println!("{}", response.content);
+                            print_tool_calls(&response);
+                            Ok(())
+                        },
+                        Ok(None) => {
+                            println!("Recorded result for tool call {}; waiting for the remaining results.", call_id);
+                            Ok(())
+                        },
+                        Err(err) => {
+                            print_client_error(&err);
+                            Err(err)
+                        }
+                    }
+                },
+
+                // Summarize older messages to save context
+                Some("compact") => {
+                    let mut conversation_id = None;
+                    let mut client_name = None;
+
+                    if args.len() > 3 && !args[3].starts_with('-') {
+                        conversation_id = Some(args[3].clone());
+                    }
+                    for i in 3..args.len() {
+                        if args[i] == "-c" && i + 1 < args.len() {
+                            client_name = Some(args[i + 1].clone());
+                        }
+                    }
+
+                    match clients::compact_conversation(conversation_id.as_deref(), client_name.as_deref()) {
+                        Ok(true) => {
+                            println!("Summarized the earlier messages; they will be sent as a summary from now on.");
+                            Ok(())
+                        },
+                        Ok(false) => {
+                            println!("Nothing to summarize yet.");
+                            Ok(())
+                        },
+                        Err(err) => {
+                            print_client_error(&err);
+                            Err(err)
+                        }
+                    }
+                },
+
+                // Branch a conversation off at a message
+                Some("fork") => {
+                    let usage = "Usage: spi chat fork <conversation_id> [--at <message_index>]";
+
+                    let conversation_id = match args.get(3) {
+                        Some(id) if !id.starts_with('-') => id.clone(),
+                        _ => return Err(anyhow!(usage)),
+                    };
+
+                    let mut message_index = None;
+                    for i in 4..args.len() {
+                        if args[i] == "--at" && i + 1 < args.len() {
+                            message_index = Some(args[i + 1].parse().map_err(|_| anyhow!("Invalid message index: {}", args[i + 1]))?);
+                        }
+                    }
+
+                    let mut history = sharpi::core::history::load_history()?;
+                    let conversation_id = history.resolve(&conversation_id)?;
+                    match history.fork_conversation(&conversation_id, message_index) {
+                        Ok((id, conversation)) => {
+                            println!("Created fork: {} (ID: {}) with {} messages", conversation.title, id, conversation.messages.len());
+                            println!("It is now the active conversation.");
                             Ok(())
                         },
                         Err(err) => {
-                            eprintln!("Error calling AI API: {}", err);
-                            eprintln!("\nMake sure your configuration is set up correctly:");
-                            eprintln!("Run 'spi init' to create a default configuration file");
-                            eprintln!("Then edit ~/.sharpi/config.toml with your API keys");
+                            eprintln!("Error forking conversation: {}", err);
                             Err(err)
                         }
                     }
                 },
 
+                // Give a conversation a title of the user's choosing
+                Some("rename") => {
+                    let (conversation_id, title) = match (args.get(3), args.get(4)) {
+                        (Some(id), Some(_)) => (id.clone(), args[4..].join(" ")),
+                        _ => return Err(anyhow!("Usage: spi chat rename <conversation_id> <title>")),
+                    };
+                    if title.trim().is_empty() {
+                        return Err(anyhow!("The title must not be empty"));
+                    }
+
+                    let history = sharpi::core::history::load_history()?;
+                    let conversation_id = history.resolve(&conversation_id)?;
+                    let _lock = history.lock_conversation(&conversation_id)?;
+                    let mut conversation = history.get_conversation(&conversation_id)?;
+                    let old_title = std::mem::replace(&mut conversation.title, title.trim().to_string());
+                    history.save_conversation(&conversation_id, &conversation)?;
+
+                    println!("Renamed \"{}\" to \"{}\"", old_title, conversation.title);
+                    Ok(())
+                },
+
+                // Label a conversation, or take labels off with --remove
+                Some("tag") => {
+                    let usage = "Usage: spi chat tag <conversation_id> [--remove] <tag>...";
+                    let conversation_id = match args.get(3) {
+                        Some(id) if !id.starts_with('-') => id.clone(),
+                        _ => return Err(anyhow!(usage)),
+                    };
+                    let remove = args[4..].iter().any(|arg| arg == "--remove" || arg == "-r");
+                    let tags: Vec<&String> = args[4..].iter().filter(|arg| !arg.starts_with('-')).collect();
+                    if tags.is_empty() {
+                        return Err(anyhow!(usage));
+                    }
+
+                    let (_, conversation) = modify_conversation(&conversation_id, |conversation| {
+                        for tag in &tags {
+                            if remove {
+                                conversation.remove_tag(tag);
+                            } else {
+                                conversation.add_tag(tag);
+                            }
+                        }
+                    })?;
+
+                    if conversation.tags.is_empty() {
+                        println!("{} has no tags", conversation.title);
+                    } else {
+                        println!("{} is tagged: {}", conversation.title, conversation.tags.join(", "));
+                    }
+                    Ok(())
+                },
+
+                // Keep a conversation at the top of the list, or hide it from it
+                Some(verb @ ("pin" | "unpin" | "archive" | "unarchive")) => {
+                    let conversation_id = match args.get(3) {
+                        Some(id) => id.clone(),
+                        None => return Err(anyhow!("Usage: spi chat {} <conversation_id>", verb)),
+                    };
+
+                    let (conversation_id, conversation) = modify_conversation(&conversation_id, |conversation| match verb {
+                        "pin" => conversation.pinned = true,
+                        "unpin" => conversation.pinned = false,
+                        "archive" => conversation.archived = true,
+                        _ => conversation.archived = false,
+                    })?;
+
+                    let done = match verb {
+                        "pin" => "Pinned",
+                        "unpin" => "Unpinned",
+                        "archive" => "Archived",
+                        _ => "Unarchived",
+                    };
+                    println!("{} {} (ID: {})", done, conversation.title, conversation_id);
+                    Ok(())
+                },
+
+                // Write a conversation out in a portable format
+                Some("export") => {
+                    let mut conversation_id = None;
+                    let mut format = ExportFormat::Markdown;
+                    let mut output = None;
+
+                    if args.len() > 3 && !args[3].starts_with('-') {
+                        conversation_id = Some(args[3].clone());
+                    }
+                    for i in 3..args.len() {
+                        if args[i] == "--format" && i + 1 < args.len() {
+                            format = args[i + 1].parse()?;
+                        }
+                        if args[i] == "-o" && i + 1 < args.len() {
+                            output = Some(args[i + 1].clone());
+                        }
+                    }
+
+                    let history = sharpi::core::history::load_history()?;
+                    let conversation_id = match conversation_id {
+                        Some(reference) => history.resolve(&reference)?,
+                        None => match history.active_conversation_id.clone() {
+                            Some(id) => id,
+                            None => return Err(anyhow!("No active conversation. Use: spi chat export <conversation_id> --format md|html|jsonl|openai")),
+                        },
+                    };
+
+                    let conversation = history.get_conversation(&conversation_id)?;
+                    let exported = export::export(&conversation, format)?;
+
+                    match output {
+                        Some(path) => {
+                            std::fs::write(&path, exported).map_err(|err| anyhow!("Failed to write {}: {}", path, err))?;
+                            eprintln!("Exported '{}' to {}", conversation.title, path);
+                        },
+                        None => print!("{}", exported),
+                    }
+                    Ok(())
+                },
+
+                // Bring in conversations from ChatGPT or OpenAI-format files
+                Some("import") => {
+                    let path = match args.get(3) {
+                        Some(path) => path.clone(),
+                        None => return Err(anyhow!("Usage: spi chat import <conversations.json|file.jsonl>")),
+                    };
+
+                    let content = std::fs::read_to_string(&path).map_err(|err| anyhow!("Failed to read {}: {}", path, err))?;
+                    let conversations = sharpi::core::import::import(&content)?;
+
+                    // Conversations with an ID that is already taken were imported
+                    // before (or collide by chance); either way, local ones are kept.
+                    let history = sharpi::core::history::load_history()?;
+                    let mut skipped = 0;
+                    for (id, conversation) in &conversations {
+                        if history.store().conversation_exists(id)? {
+                            skipped += 1;
+                            continue;
+                        }
+                        history.save_conversation(id, conversation)?;
+                    }
+
+                    println!("Imported {} conversations from {}", conversations.len() - skipped, path);
+                    if skipped > 0 {
+                        println!("Skipped {} whose ID is already in the history", skipped);
+                    }
+                    Ok(())
+                },
+
+                // Search titles and messages
+                Some("search") => {
+                    let mut words = Vec::new();
+                    let mut limit = 20;
+
+                    let mut i = 3;
+                    while i < args.len() {
+                        if args[i] == "-n" && i + 1 < args.len() {
+                            limit = args[i + 1].parse().map_err(|_| anyhow!("Invalid limit: {}", args[i + 1]))?;
+                            i += 2;
+                        } else {
+                            words.push(args[i].as_str());
+                            i += 1;
+                        }
+                    }
+
+                    if words.is_empty() {
+                        return Err(anyhow!("Usage: spi chat search <query> [-n <limit>]"));
+                    }
+
+                    let history = sharpi::core::history::load_history()?;
+                    let hits = history.search(&words.join(" "), limit)?;
+
+                    if hits.is_empty() {
+                        println!("No matches found.");
+                    }
+                    for hit in &hits {
+                        match hit.message_index {
+                            Some(index) => println!("{} - {} (message {}):", hit.conversation_id, hit.title, index),
+                            None => println!("{} - {} (title):", hit.conversation_id, hit.title),
+                        }
+                        println!("    {}", hit.snippet);
+                    }
+                    Ok(())
+                },
+
+                // Archive or delete conversations past the [history] retention limits
+                Some("gc") => {
+                    let mut policy = RetentionPolicy::from_config(&config::load_history_config()?)?;
+                    let mut dry_run = false;
+                    for arg in &args[3..] {
+                        match arg.as_str() {
+                            "--dry-run" | "-n" => dry_run = true,
+                            "--archive" => policy.action = ExpireAction::Archive,
+                            "--delete" => policy.action = ExpireAction::Delete,
+                            _ => return Err(anyhow!("Usage: spi chat gc [--dry-run] [--archive|--delete]")),
+                        }
+                    }
+
+                    let mut history = sharpi::core::history::load_history()?;
+                    let report = retention::collect_garbage(&mut history, &policy, dry_run)?;
+
+                    let (would, verb) = match (dry_run, policy.action) {
+                        (true, ExpireAction::Archive) => ("Would archive", "archive"),
+                        (true, ExpireAction::Delete) => ("Would delete", "delete"),
+                        (false, ExpireAction::Archive) => ("Archived", "archive"),
+                        (false, ExpireAction::Delete) => ("Deleted", "delete"),
+                    };
+
+                    if report.cleared_dangling_active {
+                        println!("{} the active conversation, which no longer exists.",
+                            if dry_run { "Would clear" } else { "Cleared" });
+                    }
+                    if policy.is_unlimited() {
+                        println!("No retention limits are set; add max_age_days, max_conversations or max_bytes under [history] in ~/.sharpi/config.toml.");
+                    } else if report.expired.is_empty() {
+                        println!("No conversations to {}.", verb);
+                    } else {
+                        for conversation in &report.expired {
+                            println!("  {} - {} (updated: {}, {} bytes)",
+                                conversation.id,
+                                conversation.metadata.title,
+                                conversation.metadata.updated_at.format("%Y-%m-%d %H:%M"),
+                                conversation.size
+                            );
+                        }
+                        println!("{} {} conversations ({} bytes).", would, report.expired.len(), report.bytes());
+                    }
+                    Ok(())
+                },
+
+                // Rewrite every stored conversation encrypted, or in plain text again
+                Some(command @ ("encrypt" | "decrypt")) => {
+                    let config = config::load_history_config()?;
+                    if config.encryption == "none" {
+                        return Err(anyhow!(
+                            "Set encryption under [history] in ~/.sharpi/config.toml to the key to {} with first",
+                            command
+                        ));
+                    }
+
+                    let root = sharpi::core::store::JsonDirStore::default_location()?.root().to_path_buf();
+                    let store = sharpi::core::store::open_store(&config, &root)?;
+
+                    if command == "encrypt" {
+                        let count = sharpi::core::store::rewrite_store(store.as_ref(), store.as_ref())?;
+                        // Also rewrites the listing index, which holds titles.
+                        store.list_metadata()?;
+                        println!("Encrypted {} conversations.", count);
+                    } else {
+                        let plain = sharpi::core::store::JsonDirStore::new(&root);
+                        let count = sharpi::core::store::rewrite_store(store.as_ref(), &plain)?;
+                        plain.list_metadata()?;
+                        sharpi::core::crypto::forget_key(&root)?;
+                        println!("Decrypted {} conversations.", count);
+                        println!("Set encryption = \"none\" under [history] in ~/.sharpi/config.toml now, or new changes will be encrypted again.");
+                    }
+                    Ok(())
+                },
+
+                // Copy conversations into another storage backend
+                Some("migrate") => {
+                    let target = match args.get(3).map(String::as_str) {
+                        Some(target @ ("json" | "sqlite")) => target,
+                        _ => return Err(anyhow!("Usage: spi chat migrate <json|sqlite>")),
+                    };
+                    let source = if target == "json" { "sqlite" } else { "json" };
+
+                    // The JSON store is opened with the configured encryption, so
+                    // that encrypted conversations can be read, and stay encrypted.
+                    let configured = config::load_history_config()?;
+                    if target == "sqlite" && configured.encryption != "none" {
+                        return Err(anyhow!("The sqlite backend can't be encrypted; run 'spi chat decrypt' first"));
+                    }
+                    let root = sharpi::core::store::JsonDirStore::default_location()?.root().to_path_buf();
+                    let open = |backend: &str| {
+                        let encryption = if backend == "json" { configured.encryption.clone() } else { "none".to_string() };
+                        let config = config::HistoryConfig { backend: backend.to_string(), encryption, ..configured.clone() };
+                        sharpi::core::store::open_store(&config, &root)
+                    };
+
+                    let copied = sharpi::core::store::copy_store(open(source)?.as_ref(), open(target)?.as_ref())?;
+                    println!("Copied {} conversations from the {} store to the {} store.", copied, source, target);
+                    println!("Set backend = \"{}\" under [history] in ~/.sharpi/config.toml to use it.", target);
+                    Ok(())
+                },
+
                 // Remove conversation
                 Some("rm") => {
                     if args.len() <= 3 {
@@ -236,6 +849,7 @@ fn main() -> Result<()> {
 
                     match sharpi::core::history::load_history() {
                         Ok(mut history) => {
+                            let conversation_id = history.resolve(&conversation_id)?;
                             match history.remove_conversation(&conversation_id) {
                                 Ok(()) => {
                                     println!("Removed conversation with ID: {}", conversation_id);
@@ -264,6 +878,7 @@ fn main() -> Result<()> {
 
                     match sharpi::core::history::load_history() {
                         Ok(mut history) => {
+                            let conversation_id = history.resolve(&conversation_id)?;
                             match history.set_active_conversation(conversation_id.clone()) {
                                 Ok(true) => {
                                     println!("Set active conversation to ID: {}", conversation_id);
@@ -306,6 +921,131 @@ fn main() -> Result<()> {
             }
         },
 
+        Some("models") => {
+            let subcommand = args.get(2).cloned();
+            let mut client_name = None;
+
+            for i in 3..args.len() {
+                if args[i] == "-c" && i + 1 < args.len() {
+                    client_name = Some(args[i + 1].clone());
+                }
+            }
+
+            match subcommand.as_deref() {
+                Some("ls") | Some("list") => {
+                    let (client, _) = clients::load_client(client_name.as_deref())?;
+                    let models = client.list_models()?;
+
+                    if models.is_empty() {
+                        println!("No models available.");
+                    }
+                    for model in models {
+                        let size = model.size
+                            .map(|bytes| format!("{:.1} GB", bytes as f64 / 1e9))
+                            .unwrap_or_default();
+                        println!("  {:<40} {:>10}  {}", model.name, size, model.modified_at.unwrap_or_default());
+                    }
+                    Ok(())
+                },
+                Some("pull") => {
+                    let name = match args.get(3) {
+                        Some(name) if !name.starts_with('-') => name.clone(),
+                        _ => return Err(anyhow!("Usage: spi models pull <model> [-c <client>]")),
+                    };
+
+                    let (client, _) = clients::load_client(client_name.as_deref())?;
+                    let mut last_status = String::new();
+                    let mut on_progress_line = false;
+                    client.pull_model(&name, &mut |progress| {
+                        match (progress.completed, progress.total) {
+                            (Some(completed), Some(total)) if total > 0 => {
+                                print!("\r{}: {:.0}%", progress.status, completed as f64 * 100.0 / total as f64);
+                                let _ = io::stdout().flush();
+                                on_progress_line = true;
+                            },
+                            _ if progress.status != last_status => {
+                                if on_progress_line {
+                                    println!();
+                                    on_progress_line = false;
+                                }
+                                println!("{}", progress.status);
+                            },
+                            _ => {},
+                        }
+                        last_status = progress.status.clone();
+                    })?;
+                    if on_progress_line {
+                        println!();
+                    }
+                    println!("Pulled model {}", name);
+                    Ok(())
+                },
+                _ => {
+                    println!("SharPi Models - Model Management");
+                    println!();
+                    println!("USAGE:");
+                    println!("  spi models COMMAND [-c <client>]");
+                    println!();
+                    println!("COMMANDS:");
+                    println!("  ls                        List models served by the client (alias: list)");
+                    println!("  pull <model>              Download a model to a local server (Ollama)");
+                    println!("  help                      Show this help message");
+                    Ok(())
+                }
+            }
+        },
+
+        Some("usage") => {
+            let mut since = None;
+            let mut until = None;
+
+            for i in 2..args.len() {
+                match args[i].as_str() {
+                    "--since" if i + 1 < args.len() => since = Some(parse_date(&args[i + 1])?),
+                    "--until" if i + 1 < args.len() => until = Some(parse_date(&args[i + 1])?),
+                    "--month" if i + 1 < args.len() => {
+                        let first = parse_date(&format!("{}-01", args[i + 1]))?;
+                        let next = first
+                            .checked_add_months(chrono::Months::new(1))
+                            .ok_or_else(|| anyhow!("Invalid month: {}", args[i + 1]))?;
+                        since = Some(first);
+                        until = next.pred_opt();
+                    },
+                    "help" | "--help" => {
+                        print_usage_help();
+                        return Ok(());
+                    },
+                    _ => {},
+                }
+            }
+
+            let config = config::load_config()?;
+            let history = sharpi::core::history::load_history()?;
+            let conversations = history.load_all_conversations()?;
+            let report = usage::build_report(
+                &conversations,
+                |model| config.pricing_for(model).copied(),
+                since,
+                until,
+            );
+
+            if report.total.usage.total_tokens == 0 {
+                println!("No recorded token usage in this period.");
+                return Ok(());
+            }
+
+            print_usage_table("By conversation", &report.by_conversation);
+            print_usage_table("By model", &report.by_model);
+            print_usage_table("By day", &report.by_day);
+            print_usage_table("Total", std::slice::from_ref(&report.total));
+
+            if report.total.unpriced_tokens > 0 {
+                println!("\n{} tokens used models without a [pricing] entry and are not included in costs.",
+                    report.total.unpriced_tokens);
+            }
+            Ok(())
+        },
+
         Some("daemon") => {
             let subcommand = args.get(2).cloned();
 
@@ -324,10 +1064,10 @@ fn main() -> Result<()> {
                 },
                 Some("help") => {
                     println!("SharPi Daemon - Background Service Management");
-                    println!("");
+                    println!();
                     println!("USAGE:");
                     println!("  spi daemon COMMAND");
-                    println!("");
+                    println!();
                     println!("COMMANDS:");
                     println!("  start                     Start the SharPi daemon");
                     println!("  stop                      Stop the SharPi daemon");
@@ -362,33 +1102,187 @@ fn main() -> Result<()> {
     }
 }
 
+/// Prints log messages from the library to stderr, so that the user sees
+/// why a command is waiting (on a lock or a retry) or what it left out.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Error => eprintln!("error: {}", record.args()),
+            log::Level::Warn => eprintln!("warning: {}", record.args()),
+            _ => eprintln!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Shows info messages and above, or the level set in `SHARPI_LOG` (e.g.
+/// "debug" to see requests, or "off").
+fn init_logging() {
+    static LOGGER: StderrLogger = StderrLogger;
+
+    let level = env::var("SHARPI_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Loads a conversation, applies `change` and saves it, holding the
+/// conversation's lock throughout.
+fn modify_conversation(reference: &str, change: impl FnOnce(&mut Conversation)) -> Result<(String, Conversation)> {
+    let history = sharpi::core::history::load_history()?;
+    let id = history.resolve(reference)?;
+    let _lock = history.lock_conversation(&id)?;
+
+    let mut conversation = history.get_conversation(&id)?;
+    change(&mut conversation);
+    history.save_conversation(&id, &conversation)?;
+
+    Ok((id, conversation))
+}
+
+fn parse_date(value: &str) -> Result<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| anyhow!("Invalid date '{}', expected YYYY-MM-DD", value))
+}
+
+fn print_usage_table(heading: &str, lines: &[usage::UsageLine]) {
+    println!("\n{}:", heading);
+    for line in lines {
+        let unpriced = if line.unpriced_tokens > 0 { " *" } else { "" };
+        println!("  {:<48} {:>10} tokens (in: {}, out: {})  ${:.4}{}",
+            line.label,
+            line.usage.total_tokens,
+            line.usage.prompt_tokens,
+            line.usage.completion_tokens,
+            line.cost,
+            unpriced
+        );
+    }
+}
+
+fn print_usage_help() {
+    println!("SharPi Usage - Token and Cost Reports");
+    println!();
+    println!("USAGE:");
+    println!("  spi usage [OPTIONS]");
+    println!();
+    println!("OPTIONS:");
+    println!("  --since YYYY-MM-DD        Only count messages from this day on");
+    println!("  --until YYYY-MM-DD        Only count messages up to this day");
+    println!("  --month YYYY-MM           Only count messages from this month");
+    println!("  help                      Show this help message");
+    println!();
+    println!("Costs use the [pricing.\"<model>\"] entries in ~/.sharpi/config.toml.");
+    println!("Rows marked * include tokens from models without a price.");
+}
+
+fn print_tool_calls(response: &clients::ChatResponse) {
+    if response.tool_calls.is_empty() {
+        return;
+    }
+
+    println!("\nThe model requested tool calls:");
+    for call in &response.tool_calls {
+        println!("  [{}] {}({})", call.id, call.name, call.arguments);
+    }
+    println!("Reply with: spi chat tool-result <call_id> -m \"output\"");
+}
+
+fn print_client_error(err: &anyhow::Error) {
+    eprintln!("Error calling AI API: {:#}", err);
+    match err.downcast_ref::<ClientError>() {
+        Some(ClientError::StreamInterrupted { partial, .. }) if !partial.is_empty() => {
+            eprintln!("\nThe partial response was saved to the conversation.");
+        },
+        Some(ClientError::ContextLengthExceeded(_)) => {
+            eprintln!("\nThe conversation is too long for this model.");
+            eprintln!("Summarize it with 'spi chat compact', set context_window for the client,");
+            eprintln!("or start a new one with 'spi chat new -t \"title\"'");
+        },
+        Some(ClientError::Authentication(_)) | None => {
+            eprintln!("\nMake sure your configuration is set up correctly:");
+            eprintln!("Run 'spi init' to create a default configuration file");
+            eprintln!("Then edit ~/.sharpi/config.toml with your API keys");
+        },
+        Some(_) => {},
+    }
+}
+
 fn print_chat_help() {
     println!("SharPi Chat - Conversation Management");
-    println!("");
+    println!();
     println!("USAGE:");
     println!("  spi chat COMMAND [OPTIONS]");
-    println!("");
+    println!();
     println!("COMMANDS:");
     println!("  send -m \"message\"         Send a message to the active conversation");
     println!("  send <id> -m \"msg\"        Send a message in specific conversation");
-    println!("  ls                        List all conversations (alias: list)");
+    println!("  send ... -c <client>      Use a configured client other than the default");
+    println!("  send ... --no-stream      Wait for the full response instead of streaming it");
+    println!("  retry [<id>]              Regenerate the reply to the last message (also -c, --no-stream)");
+    println!("  edit [<id>] <index> -m \"text\"");
+    println!("                            Rewrite a message and drop the messages after it");
+    println!("  tool-result <call_id> -m \"output\"");
+    println!("                            Return a tool call's output to the model");
+    println!("  compact [<id>] [-c <client>]");
+    println!("                            Summarize all but the latest turn to save context");
+    println!("  ls                        List all conversations, most recently updated first (alias: list)");
+    println!("  ls --sort updated|created|title [-n <limit>] [--since YYYY-MM-DD] [--until YYYY-MM-DD]");
+    println!("                            Sort, limit and filter by date (of creation with --sort created)");
+    println!("  ls --tag <tag> [-a]       Only conversations with a tag; -a includes archived ones");
     println!("  new -t \"title\"            Create a new conversation");
+    println!("  new ... --system \"prompt\"  Give the conversation a system prompt");
+    println!("  new ... --model <model>   Override the client's model (also --temperature, --max-tokens)");
     println!("  show                      Show active conversation details");
     println!("  show <id>                 Show specific conversation details");
+    println!("  rename <id> <title>       Change a conversation's title");
+    println!("  tag <id> [--remove] <tag>...");
+    println!("                            Add tags to a conversation, or remove them");
+    println!("  pin <id>                  Keep a conversation at the top of the list (undo: unpin)");
+    println!("  archive <id>              Hide a conversation from the list (undo: unarchive)");
+    println!("  fork <id> [--at <index>]  Copy a conversation up to a message into a new one");
+    println!("  export [<id>] [--format md|html|jsonl|openai] [-o <file>]");
+    println!("                            Write a conversation out as Markdown (default), HTML or JSON lines");
+    println!("  import <file>             Import a ChatGPT conversations.json or OpenAI-format JSONL file");
+    println!("  search <query> [-n <limit>]");
+    println!("                            Find conversations and messages containing all words");
+    println!("  migrate <json|sqlite>     Copy all conversations into the given storage backend");
+    println!("  gc [--dry-run] [--archive|--delete]");
+    println!("                            Archive or delete conversations past the [history] limits");
+    println!("  encrypt                   Encrypt stored conversations with the [history] encryption key");
+    println!("  decrypt                   Store conversations in plain text again");
     println!("  rm <id>                   Remove a conversation");
     println!("  use <id>                  Set as active conversation");
     println!("  help                      Show this help message");
+    println!();
+    println!("<id> may be a unique ID prefix of at least 4 characters, @last or @prev");
+    println!("(the most recently updated conversations) or an exact title.");
 }
 
 fn print_help(program: &str) {
     println!("SharPi - AI Coding Assistant");
-    println!("");
+    println!();
     println!("USAGE:");
     println!("  {} COMMAND [OPTIONS]", program);
-    println!("");
+    println!();
     println!("COMMANDS:");
     println!("  init [command]            Configuration management (run 'spi init help')");
     println!("  chat [command]            Conversation management (run 'spi chat help')");
+    println!("  usage [options]           Token usage and cost report (run 'spi usage help')");
+    println!("  models [command]          List or pull models (run 'spi models help')");
     println!("  -i                        Enter interactive mode");
     println!("  daemon [command]          Daemon management (run 'spi daemon help')");
     println!("  --help, -h                Show this help message");
@@ -0,0 +1,343 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use super::sse::SseReader;
+use super::{
+    interrupted, post_json, read_json, ChatMessage, ChatRequest, ChatResponse, ClientError, ClientResult, LlmClient,
+    RetryPolicy, ToolCall, Usage,
+};
+use crate::config::ClientConfig;
+use crate::core::history::Role;
+use serde_json::{json, Value};
+use std::io::BufReader;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Client for the Anthropic Messages API (`/v1/messages`).
+pub struct AnthropicClient {
+    config: ClientConfig,
+}
+
+impl AnthropicClient {
+    pub fn new(config: ClientConfig) -> Self {
+        Self { config }
+    }
+
+    fn endpoint(&self) -> String {
+        let base_url = if self.config.api_url.ends_with('/') {
+            self.config.api_url.clone()
+        } else {
+            format!("{}/", self.config.api_url)
+        };
+
+        if base_url.ends_with("messages/") {
+            base_url
+        } else {
+            format!("{}messages", base_url)
+        }
+    }
+
+    fn post(&self, body: &Value) -> ClientResult<ureq::Response> {
+        let request = ureq::post(&self.endpoint())
+            .set("x-api-key", &self.config.api_key)
+            .set("anthropic-version", ANTHROPIC_VERSION);
+        post_json(request, body, &RetryPolicy::from_config(&self.config))
+    }
+
+    /// Anthropic takes system prompts as a top-level field rather than as
+    /// messages, and rejects consecutive turns from the same role, so system
+    /// messages are pulled out and adjacent turns are merged here. Tool
+    /// results travel as `tool_result` blocks in a user turn.
+    fn request_body(&self, request: &ChatRequest) -> Value {
+        let mut system = Vec::new();
+        let mut messages: Vec<Value> = Vec::new();
+
+        for msg in &request.messages {
+            if msg.role == Role::System {
+                system.push(msg.content.as_str());
+                continue;
+            }
+
+            let role = if msg.role == Role::Tool { Role::User } else { msg.role };
+            let blocks = content_blocks(msg);
+
+            match messages.last_mut() {
+                Some(last) if last["role"] == role.as_str() => {
+                    if let Some(content) = last["content"].as_array_mut() {
+                        content.extend(blocks);
+                    }
+                },
+                _ => messages.push(json!({
+                    "role": role,
+                    "content": blocks
+                })),
+            }
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens,
+            "temperature": request.temperature
+        });
+
+        if !system.is_empty() {
+            body["system"] = Value::String(system.join("\n\n"));
+        }
+
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "input_schema": tool.parameters
+                }))
+                .collect();
+            body["tools"] = Value::Array(tools);
+        }
+
+        body
+    }
+}
+
+fn content_blocks(msg: &ChatMessage) -> Vec<Value> {
+    if let Some(id) = &msg.tool_call_id {
+        return vec![json!({
+            "type": "tool_result",
+            "tool_use_id": id,
+            "content": msg.content
+        })];
+    }
+
+    let mut blocks = Vec::new();
+    if !msg.content.is_empty() {
+        blocks.push(json!({"type": "text", "text": msg.content}));
+    }
+
+    for call in &msg.tool_calls {
+        let input: Value = serde_json::from_str(&call.arguments).unwrap_or_else(|_| json!({}));
+        blocks.push(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": call.name,
+            "input": input
+        }));
+    }
+
+    blocks
+}
+
+fn extract_tool_calls(parsed: &Value) -> Vec<ToolCall> {
+    parsed["content"]
+        .as_array()
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|block| block["type"] == "tool_use")
+                .map(|block| ToolCall {
+                    id: block["id"].as_str().unwrap_or_default().to_string(),
+                    name: block["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: block["input"].to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Concatenates the `text` content blocks of a Messages API response.
+fn extract_text(parsed: &Value) -> Option<String> {
+    let blocks = parsed["content"].as_array()?;
+
+    let text: String = blocks
+        .iter()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect();
+
+    Some(text)
+}
+
+impl LlmClient for AnthropicClient {
+    fn provider(&self) -> &str {
+        "anthropic"
+    }
+
+    fn chat(&self, request: &ChatRequest) -> ClientResult<ChatResponse> {
+        let response = self.post(&self.request_body(request))?;
+
+        let parsed = read_json(response)?;
+
+        let content = extract_text(&parsed)
+            .ok_or_else(|| ClientError::InvalidResponse("Could not find content blocks in API response".to_string()))?;
+
+        let model = parsed["model"]
+            .as_str()
+            .unwrap_or(&request.model)
+            .to_string();
+
+        let tool_calls = extract_tool_calls(&parsed);
+        let usage = parsed["usage"]["input_tokens"].as_u64().map(|input_tokens| {
+            Usage::new(input_tokens, parsed["usage"]["output_tokens"].as_u64().unwrap_or(0))
+        });
+
+        Ok(ChatResponse {
+            content,
+            model,
+            tool_calls,
+            usage,
+            raw: parsed,
+        })
+    }
+
+    fn chat_stream(&self, request: &ChatRequest, on_delta: &mut dyn FnMut(&str)) -> ClientResult<ChatResponse> {
+        let mut body = self.request_body(request);
+        body["stream"] = Value::Bool(true);
+
+        let response = self.post(&body)?;
+        let events = SseReader::new(BufReader::new(response.into_reader()));
+
+        let mut content = String::new();
+        let mut model = request.model.clone();
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        // Block index of each tool call, so argument fragments find their call.
+        let mut tool_blocks = Vec::new();
+        let mut input_tokens = None;
+        let mut output_tokens = 0;
+        let mut finished = false;
+
+        for event in events {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => return Err(interrupted(content, err.to_string())),
+            };
+
+            let data: Value = match serde_json::from_str(&event.data) {
+                Ok(data) => data,
+                Err(err) => return Err(interrupted(content, format!("malformed event: {}", err))),
+            };
+
+            match data["type"].as_str().unwrap_or_default() {
+                "message_start" => {
+                    if let Some(name) = data["message"]["model"].as_str() {
+                        model = name.to_string();
+                    }
+                    input_tokens = data["message"]["usage"]["input_tokens"].as_u64();
+                },
+                "message_delta" => {
+                    if let Some(tokens) = data["usage"]["output_tokens"].as_u64() {
+                        output_tokens = tokens;
+                    }
+                },
+                "content_block_start" if data["content_block"]["type"] == "tool_use" => {
+                    tool_blocks.push(data["index"].as_u64());
+                    tool_calls.push(ToolCall {
+                        id: data["content_block"]["id"].as_str().unwrap_or_default().to_string(),
+                        name: data["content_block"]["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: String::new(),
+                    });
+                },
+                "content_block_delta" => {
+                    if let Some(text) = data["delta"]["text"].as_str() {
+                        on_delta(text);
+                        content.push_str(text);
+                    }
+                    if let Some(json) = data["delta"]["partial_json"].as_str() {
+                        if let Some(position) = tool_blocks.iter().position(|index| *index == data["index"].as_u64()) {
+                            tool_calls[position].arguments.push_str(json);
+                        }
+                    }
+                },
+                "message_stop" => {
+                    finished = true;
+                    break;
+                },
+                "error" => {
+                    let message = data["error"]["message"].as_str().unwrap_or("unknown error");
+                    return Err(interrupted(content, message.to_string()));
+                },
+                _ => {},
+            }
+        }
+
+        if !finished {
+            return Err(interrupted(content, "stream ended before completion".to_string()));
+        }
+
+        let usage = input_tokens.map(|input_tokens| Usage::new(input_tokens, output_tokens));
+
+        Ok(ChatResponse {
+            content,
+            model,
+            tool_calls,
+            usage,
+            raw: Value::Null,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_messages_become_top_level_field() {
+        let config: ClientConfig = toml::from_str(
+            "api_key = \"key\"\napi_url = \"https://api.anthropic.com/v1\"\nmodel = \"claude\"",
+        )
+        .unwrap();
+        let client = AnthropicClient::new(config.clone());
+        let request = ChatRequest::new(&config, vec![
+            ChatMessage::new(Role::System, "Be terse."),
+            ChatMessage::new(Role::User, "Hi"),
+        ]);
+
+        let body = client.request_body(&request);
+        assert_eq!(body["system"], "Be terse.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(client.endpoint(), "https://api.anthropic.com/v1/messages");
+    }
+
+    #[test]
+    fn test_extract_text_skips_non_text_blocks() {
+        let parsed = json!({
+            "content": [
+                {"type": "text", "text": "Hello"},
+                {"type": "tool_use", "id": "x"},
+                {"type": "text", "text": " world"}
+            ]
+        });
+        assert_eq!(extract_text(&parsed).as_deref(), Some("Hello world"));
+    }
+
+    #[test]
+    fn test_tool_results_are_sent_as_user_blocks() {
+        let config: ClientConfig = toml::from_str(
+            "api_key = \"key\"\napi_url = \"https://api.anthropic.com/v1\"\nmodel = \"claude\"",
+        )
+        .unwrap();
+        let client = AnthropicClient::new(config.clone());
+        let call = ToolCall {
+            id: "toolu_1".to_string(),
+            name: "shell".to_string(),
+            arguments: "{\"cmd\":\"ls\"}".to_string(),
+        };
+        let request = ChatRequest::new(&config, vec![
+            ChatMessage::new(Role::User, "List files"),
+            ChatMessage {
+                tool_calls: vec![call],
+                ..ChatMessage::new(Role::Assistant, "")
+            },
+            ChatMessage::tool_result("toolu_1", "a.txt"),
+        ]);
+
+        let body = client.request_body(&request);
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][0]["input"]["cmd"], "ls");
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "toolu_1");
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use super::retry;
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+pub type ClientResult<T> = std::result::Result<T, ClientError>;
+
+/// Details of an error response returned by a provider's API.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ApiError {
+    pub status: u16,
+    /// `error.type`, e.g. "invalid_request_error" or "rate_limit_error".
+    pub error_type: Option<String>,
+    /// `error.code`, e.g. "context_length_exceeded" (OpenAI only).
+    pub code: Option<String>,
+    pub message: String,
+    /// How long the provider asked us to wait, from the response headers.
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    /// Parses an error body in any of the shapes providers use:
+    /// `{"error": {"type", "code", "message"}}` (OpenAI, Anthropic) or
+    /// `{"error": "message"}` (Ollama). Anything else is kept verbatim.
+    pub fn parse(status: u16, body: &str) -> Self {
+        let parsed: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+        let error = &parsed["error"];
+
+        let message = error["message"]
+            .as_str()
+            .or_else(|| error.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| body.trim().to_string());
+
+        Self {
+            status,
+            error_type: error["type"].as_str().map(str::to_string),
+            code: error["code"].as_str().map(str::to_string),
+            message,
+            retry_after: None,
+        }
+    }
+
+    fn mentions_context_length(&self) -> bool {
+        let message = self.message.to_lowercase();
+        self.code.as_deref() == Some("context_length_exceeded")
+            || message.contains("context length")
+            || message.contains("context window")
+            || message.contains("prompt is too long")
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "status {}", self.status)?;
+        if let Some(error_type) = &self.error_type {
+            write!(f, ", {}", error_type)?;
+        }
+        if let Some(code) = &self.code {
+            write!(f, " ({})", code)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Errors returned by `LlmClient` implementations.
+#[derive(Debug, Clone)]
+pub enum ClientError {
+    /// The API key is missing, invalid or lacks access (401/403).
+    Authentication(ApiError),
+    /// Too many requests; worth retrying after a delay.
+    RateLimited(ApiError),
+    /// The account is out of credit or over its hard quota.
+    QuotaExceeded(ApiError),
+    /// The conversation does not fit in the model's context window.
+    ContextLengthExceeded(ApiError),
+    /// The provider rejected the request itself (bad model name, 400, 404...).
+    InvalidRequest(ApiError),
+    /// The provider failed or is overloaded (5xx, Anthropic's 529).
+    Server(ApiError),
+    /// The request never got a response: DNS, connect, TLS or I/O failure.
+    Network(String),
+    /// A response arrived but could not be understood.
+    InvalidResponse(String),
+    /// A streamed reply broke off; `partial` holds the text received so far.
+    StreamInterrupted { partial: String, reason: String },
+    /// The provider does not offer the requested operation.
+    Unsupported(String),
+}
+
+impl ClientError {
+    /// Classifies an HTTP error response.
+    pub fn from_status(status: u16, body: &str) -> Self {
+        Self::from_api_error(ApiError::parse(status, body))
+    }
+
+    fn from_api_error(error: ApiError) -> Self {
+        let status = error.status;
+
+        match status {
+            401 | 403 => ClientError::Authentication(error),
+            429 if error.code.as_deref() == Some("insufficient_quota") => ClientError::QuotaExceeded(error),
+            429 => ClientError::RateLimited(error),
+            _ if error.mentions_context_length() => ClientError::ContextLengthExceeded(error),
+            500..=599 => ClientError::Server(error),
+            _ => ClientError::InvalidRequest(error),
+        }
+    }
+
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ClientError::RateLimited(_) | ClientError::Server(_) | ClientError::Network(_)
+        )
+    }
+
+    /// How long the provider asked us to wait before retrying, if it said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.api_error().and_then(|error| error.retry_after)
+    }
+
+    /// The provider's error details, for errors that came from an HTTP response.
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            ClientError::Authentication(error)
+            | ClientError::RateLimited(error)
+            | ClientError::QuotaExceeded(error)
+            | ClientError::ContextLengthExceeded(error)
+            | ClientError::InvalidRequest(error)
+            | ClientError::Server(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Authentication(error) => write!(f, "Authentication failed ({})", error),
+            ClientError::RateLimited(error) => write!(f, "Rate limited ({})", error),
+            ClientError::QuotaExceeded(error) => write!(f, "Quota exceeded ({})", error),
+            ClientError::ContextLengthExceeded(error) => write!(f, "Context length exceeded ({})", error),
+            ClientError::InvalidRequest(error) => write!(f, "Invalid request ({})", error),
+            ClientError::Server(error) => write!(f, "Server error ({})", error),
+            ClientError::Network(message) => write!(f, "Network error while making API request: {}", message),
+            ClientError::InvalidResponse(message) => write!(f, "Invalid API response: {}", message),
+            ClientError::StreamInterrupted { reason, .. } => write!(f, "Response stream interrupted: {}", reason),
+            ClientError::Unsupported(message) => write!(f, "Unsupported operation: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<ureq::Error> for ClientError {
+    fn from(err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code, res) => {
+                let retry_after = retry::retry_after(&res);
+                let body = res.into_string()
+                    .unwrap_or_else(|_| "Could not read error response".to_string());
+                ClientError::from_api_error(ApiError {
+                    retry_after,
+                    ..ApiError::parse(code, &body)
+                })
+            },
+            ureq::Error::Transport(transport) => ClientError::Network(transport.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifies_openai_errors() {
+        let body = r#"{"error": {"message": "This model's maximum context length is 8192 tokens", "type": "invalid_request_error", "code": "context_length_exceeded"}}"#;
+        let err = ClientError::from_status(400, body);
+        assert!(matches!(err, ClientError::ContextLengthExceeded(_)));
+        assert!(!err.is_retryable());
+        assert_eq!(err.api_error().unwrap().error_type.as_deref(), Some("invalid_request_error"));
+
+        let quota = r#"{"error": {"message": "You exceeded your current quota", "type": "insufficient_quota", "code": "insufficient_quota"}}"#;
+        assert!(matches!(ClientError::from_status(429, quota), ClientError::QuotaExceeded(_)));
+        assert!(ClientError::from_status(429, "{}").is_retryable());
+    }
+
+    #[test]
+    fn test_classifies_anthropic_and_plain_errors() {
+        let overloaded = r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#;
+        let err = ClientError::from_status(529, overloaded);
+        assert!(matches!(err, ClientError::Server(_)));
+        assert!(err.is_retryable());
+
+        let err = ClientError::from_status(401, "not json");
+        assert!(matches!(err, ClientError::Authentication(_)));
+        assert_eq!(err.api_error().unwrap().message, "not json");
+
+        let err = ClientError::from_status(404, r#"{"error": "model 'x' not found"}"#);
+        assert_eq!(err.api_error().unwrap().message, "model 'x' not found");
+    }
+}
@@ -1,4 +1,600 @@
 // Copyright (c) 2025 SharPi Contributors
 // MIT License
 
+pub mod anthropic;
+pub mod error;
+pub mod ollama;
 pub mod openai;
+pub mod retry;
+pub mod sse;
+
+pub use error::{ApiError, ClientError, ClientResult};
+pub use retry::RetryPolicy;
+
+use crate::config::{self, ClientConfig, Config};
+use crate::core::context::ContextManager;
+use crate::core::history::{self, Role};
+use crate::core::title;
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A function call requested by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON-encoded arguments, exactly as produced by the model.
+    pub arguments: String,
+}
+
+/// Token counts reported by the provider for one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// A function the model may call, described by a JSON Schema for its
+/// arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// Calls requested by an assistant message.
+    pub tool_calls: Vec<ToolCall>,
+    /// For "tool" messages, the call this is the result of.
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: &str) -> Self {
+        Self {
+            role,
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn tool_result(tool_call_id: &str, content: &str) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub tools: Vec<ToolDefinition>,
+}
+
+impl ChatRequest {
+    pub fn new(client_config: &ClientConfig, messages: Vec<ChatMessage>) -> Self {
+        Self {
+            model: client_config.model.clone(),
+            messages,
+            max_tokens: client_config.max_tokens,
+            temperature: client_config.temperature,
+            tools: Vec::new(),
+        }
+    }
+
+    /// Builds a request from a conversation's messages. The conversation's
+    /// system prompt goes first and its settings take precedence over the
+    /// client's. If it has a summary, that is sent as a system message in
+    /// place of the messages it covers.
+    pub fn from_conversation(client_config: &ClientConfig, conversation: &history::Conversation) -> Self {
+        let mut messages = Vec::new();
+        let mut start = 0;
+
+        if let Some(system_prompt) = &conversation.system_prompt {
+            messages.push(ChatMessage::new(Role::System, system_prompt));
+        }
+
+        if let Some(summary) = &conversation.summary {
+            messages.push(ChatMessage::new(
+                Role::System,
+                &format!("Summary of the earlier conversation:\n{}", summary.content),
+            ));
+            start = summary.covers.min(conversation.messages.len());
+        }
+
+        messages.extend(conversation.messages[start..].iter().map(|msg| ChatMessage {
+            tool_calls: msg.tool_calls.clone(),
+            tool_call_id: msg.tool_call_id.clone(),
+            ..ChatMessage::new(msg.role, &msg.content)
+        }));
+
+        let settings = &conversation.settings;
+        Self {
+            model: settings.model.clone().unwrap_or_else(|| client_config.model.clone()),
+            max_tokens: settings.max_tokens.unwrap_or(client_config.max_tokens),
+            temperature: settings.temperature.unwrap_or(client_config.temperature),
+            ..Self::new(client_config, messages)
+        }
+    }
+
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+    pub content: String,
+    pub model: String,
+    /// Calls the model wants made; their results go back as "tool" messages.
+    pub tool_calls: Vec<ToolCall>,
+    /// Token counts, when the provider reports them.
+    pub usage: Option<Usage>,
+    /// The provider's response body, for fields not yet modelled here.
+    pub raw: Value,
+}
+
+/// Posts a JSON body, turning HTTP and transport failures into `ClientError`s
+/// and retrying them according to `policy`.
+pub(crate) fn post_json(request: ureq::Request, body: &Value, policy: &RetryPolicy) -> ClientResult<ureq::Response> {
+    debug!("Sending request to: {}", request.url());
+    debug!("  Model: {}", body["model"]);
+    debug!("  Message count: {}", body["messages"].as_array().map_or(0, Vec::len));
+
+    let body = body.to_string();
+    policy.run(|| {
+        Ok(request
+            .clone()
+            .set("Content-Type", "application/json")
+            .send_string(&body)?)
+    })
+}
+
+/// Sends a GET request and parses the JSON response, retrying according to
+/// `policy`.
+pub(crate) fn get_json(request: ureq::Request, policy: &RetryPolicy) -> ClientResult<Value> {
+    debug!("Sending request to: {}", request.url());
+
+    let response = policy.run(|| Ok(request.clone().call()?))?;
+    read_json(response)
+}
+
+/// Reads and parses a JSON response body.
+pub(crate) fn read_json(response: ureq::Response) -> ClientResult<Value> {
+    let response_text = response
+        .into_string()
+        .map_err(|err| ClientError::Network(format!("Failed to read response body: {}", err)))?;
+
+    serde_json::from_str(&response_text)
+        .map_err(|err| ClientError::InvalidResponse(format!("Response is not valid JSON: {}", err)))
+}
+
+fn interrupted(partial: String, reason: String) -> ClientError {
+    ClientError::StreamInterrupted { partial, reason }
+}
+
+/// A model available from a provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub name: String,
+    /// Size on disk in bytes, for locally served models.
+    pub size: Option<u64>,
+    pub modified_at: Option<String>,
+}
+
+/// A progress update while downloading a model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullProgress {
+    pub status: String,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+}
+
+/// A chat completion backend, built from a `[clients.<name>]` config entry.
+pub trait LlmClient {
+    /// Provider identifier, e.g. "openai".
+    fn provider(&self) -> &str;
+
+    fn chat(&self, request: &ChatRequest) -> ClientResult<ChatResponse>;
+
+    /// Like `chat`, but hands each piece of text to `on_delta` as it arrives.
+    ///
+    /// Providers without streaming support deliver the whole reply as a
+    /// single delta. If the stream breaks off, the error is
+    /// `ClientError::StreamInterrupted` holding the text received so far.
+    fn chat_stream(&self, request: &ChatRequest, on_delta: &mut dyn FnMut(&str)) -> ClientResult<ChatResponse> {
+        let response = self.chat(request)?;
+        on_delta(&response.content);
+        Ok(response)
+    }
+
+    /// Models the provider can serve.
+    fn list_models(&self) -> ClientResult<Vec<ModelInfo>> {
+        Err(ClientError::Unsupported(format!("listing models is not supported by the '{}' provider", self.provider())))
+    }
+
+    /// Downloads a model so it can be served locally.
+    fn pull_model(&self, name: &str, _on_progress: &mut dyn FnMut(&PullProgress)) -> ClientResult<()> {
+        Err(ClientError::Unsupported(format!(
+            "pulling models ('{}') is not supported by the '{}' provider", name, self.provider()
+        )))
+    }
+}
+
+/// Resolves which provider implementation a config entry uses.
+///
+/// The provider is taken from the entry's `provider` key when present, and
+/// otherwise inferred from the entry name. Unrecognised names are assumed to
+/// be OpenAI-compatible endpoints.
+pub fn provider_for(name: &str, client_config: &ClientConfig) -> String {
+    match client_config.provider.as_deref() {
+        Some(provider) => provider.to_string(),
+        None => match name {
+            "anthropic" | "claude" => "anthropic".to_string(),
+            "ollama" => "ollama".to_string(),
+            _ => "openai".to_string(),
+        },
+    }
+}
+
+/// Builds the client implementation for a configured provider.
+pub fn create_client(name: &str, client_config: &ClientConfig) -> Result<Box<dyn LlmClient>> {
+    match provider_for(name, client_config).as_str() {
+        "openai" => Ok(Box::new(openai::OpenAiClient::new(client_config.clone()))),
+        "anthropic" => Ok(Box::new(anthropic::AnthropicClient::new(client_config.clone()))),
+        "ollama" => Ok(Box::new(ollama::OllamaClient::new(client_config.clone()))),
+        other => Err(anyhow::anyhow!(
+            "Unknown provider '{}' for client '{}'", other, name
+        )),
+    }
+}
+
+/// Loads the config and builds the named client, or the default one.
+pub fn load_client(client_name: Option<&str>) -> Result<(Box<dyn LlmClient>, ClientConfig)> {
+    client_from_config(&config::load_config()?, client_name)
+}
+
+fn client_from_config(config: &Config, client_name: Option<&str>) -> Result<(Box<dyn LlmClient>, ClientConfig)> {
+    let name = client_name.unwrap_or(&config.clients.default);
+    let client_config = config.get_client_config(Some(name))?.clone();
+    let client = create_client(name, &client_config)?;
+    Ok((client, client_config))
+}
+
+/// Sends a single message without touching conversation history.
+pub fn call(input: &str, client_name: Option<&str>) -> Result<ChatResponse> {
+    let (client, client_config) = load_client(client_name)?;
+    let request = ChatRequest::new(&client_config, vec![ChatMessage::new(Role::User, input)]);
+    Ok(client.chat(&request)?)
+}
+
+/// Appends `input` to a conversation, sends the whole conversation and
+/// stores the reply.
+///
+/// Uses `conversation_id` when given (making it the active conversation;
+/// anything `History::resolve` accepts will do), otherwise the active
+/// conversation, creating one if none exists. Tools defined under `[tools]`
+/// in the config are offered to the model; any calls it makes are stored on
+/// the reply and returned in `tool_calls`.
+pub fn call_with_history(input: &str, conversation_id: Option<&str>, client_name: Option<&str>) -> Result<ChatResponse> {
+    let input = input.to_string();
+    send_with_history(conversation_id, client_name, None, |conversation| {
+        conversation.add_user_message(input);
+        Ok(true)
+    })
+    .map(|response| response.expect("user messages are always sent"))
+}
+
+/// Streaming variant of `call_with_history`; `on_delta` receives the reply
+/// as it arrives.
+///
+/// The reply is persisted once the stream completes. If the stream is
+/// interrupted, the user message and any partial reply (marked as
+/// interrupted) are still saved before the error is returned.
+pub fn call_with_history_streaming(
+    input: &str,
+    conversation_id: Option<&str>,
+    client_name: Option<&str>,
+    on_delta: &mut dyn FnMut(&str),
+) -> Result<ChatResponse> {
+    let input = input.to_string();
+    send_with_history(conversation_id, client_name, Some(on_delta), |conversation| {
+        conversation.add_user_message(input);
+        Ok(true)
+    })
+    .map(|response| response.expect("user messages are always sent"))
+}
+
+/// Records the output of a tool call the model requested.
+///
+/// Once every call from the model's last message has a result, the
+/// conversation is sent back to the model and its reply returned; until
+/// then the result is only stored and `None` is returned.
+pub fn submit_tool_result(
+    tool_call_id: &str,
+    output: &str,
+    conversation_id: Option<&str>,
+    client_name: Option<&str>,
+) -> Result<Option<ChatResponse>> {
+    let tool_call_id = tool_call_id.to_string();
+    let output = output.to_string();
+    send_with_history(conversation_id, client_name, None, |conversation| {
+        if !conversation.pending_tool_calls().iter().any(|call| call.id == tool_call_id) {
+            return Err(anyhow::anyhow!("No pending tool call with ID '{}'", tool_call_id));
+        }
+        conversation.add_tool_message(tool_call_id, output);
+        Ok(conversation.pending_tool_calls().is_empty())
+    })
+}
+
+/// Generates a new reply to the last user message of a conversation.
+///
+/// An existing reply is replaced, and kept on the new one as an
+/// alternative. If the last message has no reply yet (e.g. after an edit or
+/// a failed send), one is simply generated. When `on_delta` is given the
+/// reply is streamed to it.
+pub fn retry_last_turn(
+    conversation_id: Option<&str>,
+    client_name: Option<&str>,
+    on_delta: Option<&mut dyn FnMut(&str)>,
+) -> Result<ChatResponse> {
+    send_with_history(conversation_id, client_name, on_delta, |conversation| {
+        conversation.start_retry()?;
+        Ok(true)
+    })
+    .map(|response| response.expect("retries are always sent"))
+}
+
+/// Sends `conversation`, summarizing or truncating it first if it doesn't
+/// fit the context window.
+fn request_reply(
+    client: &dyn LlmClient,
+    client_config: &ClientConfig,
+    config: &Config,
+    conversation: &mut history::Conversation,
+    on_delta: Option<&mut dyn FnMut(&str)>,
+) -> Result<ChatResponse> {
+    let tools = config.tool_definitions()?;
+    let mut request = ChatRequest::from_conversation(client_config, conversation).with_tools(tools.clone());
+    let context = ContextManager::new(client_config, &request);
+
+    if !context.fits(&request)
+        && client_config.summarize_history
+        && context.summarize(client, client_config, conversation, false)?
+    {
+        request = ChatRequest::from_conversation(client_config, conversation).with_tools(tools);
+    }
+
+    let dropped = context.truncate(&mut request);
+    if dropped > 0 {
+        warn!("Left the {} oldest messages out of the request to fit the context window", dropped);
+    }
+
+    let result = match on_delta {
+        Some(on_delta) => client.chat_stream(&request, on_delta),
+        None => client.chat(&request),
+    };
+    result.map_err(|err| anyhow::Error::new(err).context(format!("Request to '{}' provider failed", client.provider())))
+}
+
+fn send_with_history(
+    conversation_id: Option<&str>,
+    client_name: Option<&str>,
+    on_delta: Option<&mut dyn FnMut(&str)>,
+    update: impl FnOnce(&mut history::Conversation) -> Result<bool>,
+) -> Result<Option<ChatResponse>> {
+    let mut history = history::load_history()?;
+    let config = config::load_config()?;
+    send_in_history(&mut history, &config, conversation_id, client_name, on_delta, update)
+}
+
+/// Loads the target conversation, lets `update` append to it and, if
+/// `update` returns true, sends the conversation and stores the reply.
+fn send_in_history(
+    history: &mut history::History,
+    config: &Config,
+    conversation_id: Option<&str>,
+    client_name: Option<&str>,
+    on_delta: Option<&mut dyn FnMut(&str)>,
+    update: impl FnOnce(&mut history::Conversation) -> Result<bool>,
+) -> Result<Option<ChatResponse>> {
+    if let Some(reference) = conversation_id {
+        let id = history.resolve(reference)?;
+        history.set_active_conversation(id)?;
+    }
+
+    let client_name = client_name.unwrap_or(&config.clients.default);
+    let (client, client_config) = client_from_config(config, Some(client_name))?;
+
+    // Another process may be sending in the same conversation; wait for it
+    // to save its reply, then start from the saved version.
+    let (id, _) = history.ensure_active_conversation()?;
+    let lock = history.lock_conversation(&id)?;
+    let mut conversation = history.get_conversation(&id)?;
+    if !update(&mut conversation)? {
+        history.save_conversation(&id, &conversation)?;
+        history.save()?;
+        return Ok(None);
+    }
+
+    let response = match request_reply(client.as_ref(), &client_config, config, &mut conversation, on_delta) {
+        Ok(response) => response,
+        Err(err) => {
+            // Keep the user's message even though it has no reply, so it
+            // isn't lost; `spi chat retry` sends it again.
+            match err.downcast_ref::<ClientError>() {
+                Some(ClientError::StreamInterrupted { partial, .. }) if !partial.is_empty() => {
+                    conversation.add_interrupted_assistant_message(partial.clone());
+                }
+                _ => conversation.cancel_retry(),
+            }
+            history.save_conversation(&id, &conversation)?;
+            history.save()?;
+            return Err(err);
+        }
+    };
+
+    conversation.add_assistant_reply(&response);
+    history.save_conversation(&id, &conversation)?;
+    history.save()?;
+    drop(lock);
+
+    // Best effort: without a title, the conversation keeps its placeholder
+    // until the next reply.
+    if title::needs_title(&conversation) {
+        let title_config = ClientConfig { max_retries: 0, ..client_config.clone() };
+        let titled = create_client(client_name, &title_config)
+            .and_then(|title_client| title::auto_title(history, &id, title_client.as_ref(), &title_config));
+        if let Err(err) = titled {
+            warn!("Failed to title the conversation: {:#}", err);
+        }
+    }
+
+    Ok(Some(response))
+}
+
+/// Summarizes all but the latest turn of a conversation now, rather than
+/// waiting for it to outgrow the context window. Returns false if there was
+/// nothing to summarize.
+pub fn compact_conversation(conversation_id: Option<&str>, client_name: Option<&str>) -> Result<bool> {
+    let history = history::load_history()?;
+    let id = match conversation_id {
+        Some(reference) => history.resolve(reference)?,
+        None => history
+            .active_conversation_id
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No active conversation"))?,
+    };
+    let _lock = history.lock_conversation(&id)?;
+    let mut conversation = history.get_conversation(&id)?;
+
+    let (client, client_config) = load_client(client_name)?;
+    let request = ChatRequest::from_conversation(&client_config, &conversation);
+    let context = ContextManager::new(&client_config, &request);
+
+    if !context.summarize(client.as_ref(), &client_config, &mut conversation, true)? {
+        return Ok(false);
+    }
+
+    history.save_conversation(&id, &conversation)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::retry::tests::{http_response, mock_server};
+    use crate::core::history::History;
+    use crate::core::store::MemoryStore;
+
+    fn client_config(extra: &str) -> ClientConfig {
+        toml::from_str(&format!(
+            "api_key = \"key\"\napi_url = \"http://localhost\"\nmodel = \"test-model\"\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_create_client_defaults_to_openai() {
+        let client = create_client("custom", &client_config("")).unwrap();
+        assert_eq!(client.provider(), "openai");
+    }
+
+    #[test]
+    fn test_create_client_rejects_unknown_provider() {
+        assert!(create_client("custom", &client_config("provider = \"nope\"")).is_err());
+    }
+
+    #[test]
+    fn test_provider_inferred_from_entry_name() {
+        assert_eq!(provider_for("claude", &client_config("")), "anthropic");
+        assert_eq!(provider_for("ollama", &client_config("")), "ollama");
+        assert_eq!(provider_for("claude", &client_config("provider = \"openai\"")), "openai");
+    }
+
+    #[test]
+    fn test_conversation_settings_override_client_config() {
+        let (_, mut conversation) = history::Conversation::new("Review".to_string());
+        conversation.system_prompt = Some("You are a strict code reviewer.".to_string());
+        conversation.settings.model = Some("reviewer-model".to_string());
+        conversation.settings.temperature = Some(0.1);
+        conversation.add_user_message("Look at this diff".to_string());
+
+        let request = ChatRequest::from_conversation(&client_config("max_tokens = 321"), &conversation);
+        assert_eq!(request.model, "reviewer-model");
+        assert_eq!(request.temperature, 0.1);
+        assert_eq!(request.max_tokens, 321);
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, Role::System);
+        assert_eq!(request.messages[0].content, "You are a strict code reviewer.");
+    }
+
+    #[test]
+    fn test_failed_request_keeps_the_user_message() {
+        let ok = r#"{"model": "m", "choices": [{"message": {"content": "done"}}]}"#;
+        let (url, _) = mock_server(vec![
+            http_response("503 Service Unavailable", "", "{}"),
+            http_response("503 Service Unavailable", "", "{}"),
+            http_response("200 OK", "", ok),
+        ]);
+        let config: Config = toml::from_str(&format!(
+            "[clients]\ndefault = \"openai\"\n[clients.openai]\napi_key = \"key\"\napi_url = \"{}\"\n\
+             model = \"m\"\nmax_retries = 1\nretry_base_delay_ms = 1\n",
+            url
+        ))
+        .unwrap();
+        let mut history = History::open(Box::new(MemoryStore::new())).unwrap();
+
+        let result = send_in_history(&mut history, &config, None, None, None, |conversation| {
+            conversation.add_user_message("hello".to_string());
+            Ok(true)
+        });
+        assert!(result.is_err());
+
+        let (id, conversation) = history.ensure_active_conversation().unwrap();
+        assert_eq!(conversation.messages.len(), 1);
+        assert_eq!(conversation.messages[0].content, "hello");
+
+        // Retrying answers the saved message.
+        send_in_history(&mut history, &config, Some(&id), None, None, |conversation| {
+            conversation.start_retry()?;
+            Ok(true)
+        })
+        .unwrap();
+        let conversation = history.get_conversation(&id).unwrap();
+        assert_eq!(conversation.messages.len(), 2);
+        assert_eq!(conversation.messages[1].content, "done");
+    }
+}
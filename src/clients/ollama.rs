@@ -0,0 +1,303 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use super::{
+    get_json, interrupted, post_json, read_json, ChatMessage, ChatRequest, ChatResponse, ClientError, ClientResult,
+    LlmClient, ModelInfo, PullProgress, RetryPolicy, ToolCall, Usage,
+};
+use crate::config::ClientConfig;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+use uuid::Uuid;
+
+/// Client for a local Ollama server's native `/api/chat` endpoint.
+///
+/// The API key is optional; when set it is sent as a Bearer token, for
+/// servers sitting behind an authenticating proxy.
+pub struct OllamaClient {
+    config: ClientConfig,
+}
+
+impl OllamaClient {
+    pub fn new(config: ClientConfig) -> Self {
+        Self { config }
+    }
+
+    /// Joins `path` onto the server root. A trailing `/api` in `api_url` is
+    /// accepted, so both "http://localhost:11434" and ".../api" work.
+    fn endpoint(&self, path: &str) -> String {
+        let base_url = self.config.api_url.trim_end_matches('/');
+        let base_url = base_url.strip_suffix("/api").unwrap_or(base_url);
+        format!("{}/api/{}", base_url, path)
+    }
+
+    fn authorize(&self, request: ureq::Request) -> ureq::Request {
+        if self.config.api_key.is_empty() {
+            request
+        } else {
+            request.set("Authorization", &format!("Bearer {}", self.config.api_key))
+        }
+    }
+
+    fn post(&self, path: &str, body: &Value) -> ClientResult<ureq::Response> {
+        let request = self.authorize(ureq::post(&self.endpoint(path)));
+        post_json(request, body, &RetryPolicy::from_config(&self.config))
+    }
+
+    fn request_body(&self, request: &ChatRequest, stream: bool) -> Value {
+        let messages: Vec<Value> = request.messages.iter().map(message_json).collect();
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": stream,
+            "options": {
+                "temperature": request.temperature,
+                "num_predict": request.max_tokens
+            }
+        });
+
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.parameters
+                    }
+                }))
+                .collect();
+            body["tools"] = Value::Array(tools);
+        }
+
+        body
+    }
+}
+
+/// Ollama takes tool call arguments as objects rather than JSON strings and
+/// has no call IDs, so only the function part of each call is sent.
+fn message_json(msg: &ChatMessage) -> Value {
+    let mut message = json!({
+        "role": msg.role,
+        "content": msg.content
+    });
+
+    if !msg.tool_calls.is_empty() {
+        let calls: Vec<Value> = msg
+            .tool_calls
+            .iter()
+            .map(|call| json!({
+                "function": {
+                    "name": call.name,
+                    "arguments": serde_json::from_str::<Value>(&call.arguments).unwrap_or_else(|_| json!({}))
+                }
+            }))
+            .collect();
+        message["tool_calls"] = Value::Array(calls);
+    }
+
+    message
+}
+
+/// Ollama does not assign IDs to tool calls, so fresh ones are generated to
+/// let results be matched up with their calls.
+fn parse_tool_calls(message: &Value) -> Vec<ToolCall> {
+    message["tool_calls"]
+        .as_array()
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|call| ToolCall {
+                    id: format!("call_{}", Uuid::new_v4().simple()),
+                    name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: call["function"]["arguments"].to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_usage(chunk: &Value) -> Option<Usage> {
+    let prompt_tokens = chunk["prompt_eval_count"].as_u64()?;
+    Some(Usage::new(prompt_tokens, chunk["eval_count"].as_u64().unwrap_or(0)))
+}
+
+impl LlmClient for OllamaClient {
+    fn provider(&self) -> &str {
+        "ollama"
+    }
+
+    fn chat(&self, request: &ChatRequest) -> ClientResult<ChatResponse> {
+        let response = self.post("chat", &self.request_body(request, false))?;
+        let parsed = read_json(response)?;
+
+        let message = &parsed["message"];
+        if !message.is_object() {
+            return Err(ClientError::InvalidResponse("Could not find message in API response".to_string()));
+        }
+
+        Ok(ChatResponse {
+            content: message["content"].as_str().unwrap_or_default().to_string(),
+            model: parsed["model"].as_str().unwrap_or(&request.model).to_string(),
+            tool_calls: parse_tool_calls(message),
+            usage: parse_usage(&parsed),
+            raw: parsed,
+        })
+    }
+
+    /// Ollama streams newline-delimited JSON objects rather than SSE; the
+    /// last one has `"done": true` and carries the token counts.
+    fn chat_stream(&self, request: &ChatRequest, on_delta: &mut dyn FnMut(&str)) -> ClientResult<ChatResponse> {
+        let response = self.post("chat", &self.request_body(request, true))?;
+        let reader = BufReader::new(response.into_reader());
+
+        let mut content = String::new();
+        let mut model = request.model.clone();
+        let mut tool_calls = Vec::new();
+
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Err(interrupted(content, err.to_string())),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let chunk: Value = match serde_json::from_str(&line) {
+                Ok(chunk) => chunk,
+                Err(err) => return Err(interrupted(content, format!("malformed chunk: {}", err))),
+            };
+
+            if let Some(message) = chunk["error"].as_str() {
+                return Err(interrupted(content, message.to_string()));
+            }
+
+            if let Some(name) = chunk["model"].as_str() {
+                model = name.to_string();
+            }
+
+            if let Some(text) = chunk["message"]["content"].as_str() {
+                if !text.is_empty() {
+                    on_delta(text);
+                    content.push_str(text);
+                }
+            }
+            tool_calls.extend(parse_tool_calls(&chunk["message"]));
+
+            if chunk["done"].as_bool() == Some(true) {
+                return Ok(ChatResponse {
+                    content,
+                    model,
+                    tool_calls,
+                    usage: parse_usage(&chunk),
+                    raw: Value::Null,
+                });
+            }
+        }
+
+        Err(interrupted(content, "stream ended before completion".to_string()))
+    }
+
+    fn list_models(&self) -> ClientResult<Vec<ModelInfo>> {
+        let request = self.authorize(ureq::get(&self.endpoint("tags")));
+        let parsed = get_json(request, &RetryPolicy::from_config(&self.config))?;
+
+        let models = parsed["models"]
+            .as_array()
+            .ok_or_else(|| ClientError::InvalidResponse("Could not find models in API response".to_string()))?;
+
+        Ok(models
+            .iter()
+            .map(|model| ModelInfo {
+                name: model["name"].as_str().unwrap_or_default().to_string(),
+                size: model["size"].as_u64(),
+                modified_at: model["modified_at"].as_str().map(str::to_string),
+            })
+            .collect())
+    }
+
+    fn pull_model(&self, name: &str, on_progress: &mut dyn FnMut(&PullProgress)) -> ClientResult<()> {
+        let response = self.post("pull", &json!({"model": name, "stream": true}))?;
+        let reader = BufReader::new(response.into_reader());
+
+        for line in reader.lines() {
+            let line = line.map_err(|err| ClientError::Network(err.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let chunk: Value = serde_json::from_str(&line)
+                .map_err(|err| ClientError::InvalidResponse(format!("malformed progress update: {}", err)))?;
+
+            if let Some(message) = chunk["error"].as_str() {
+                return Err(ClientError::from_status(400, &json!({"error": message}).to_string()));
+            }
+
+            let status = chunk["status"].as_str().unwrap_or_default().to_string();
+            let done = status == "success";
+            on_progress(&PullProgress {
+                status,
+                total: chunk["total"].as_u64(),
+                completed: chunk["completed"].as_u64(),
+            });
+
+            if done {
+                return Ok(());
+            }
+        }
+
+        Err(ClientError::Network("pull stream ended before completion".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::history::Role;
+
+    fn client(api_url: &str) -> OllamaClient {
+        let config: ClientConfig = toml::from_str(&format!(
+            "provider = \"ollama\"\napi_url = \"{}\"\nmodel = \"llama3\"",
+            api_url
+        ))
+        .unwrap();
+        OllamaClient::new(config)
+    }
+
+    #[test]
+    fn test_endpoint_accepts_root_or_api_url() {
+        assert_eq!(client("http://localhost:11434").endpoint("chat"), "http://localhost:11434/api/chat");
+        assert_eq!(client("http://localhost:11434/api/").endpoint("tags"), "http://localhost:11434/api/tags");
+    }
+
+    #[test]
+    fn test_parses_tool_calls_and_usage() {
+        let chunk = json!({
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{"function": {"name": "shell", "arguments": {"cmd": "ls"}}}]
+            },
+            "done": true,
+            "prompt_eval_count": 12,
+            "eval_count": 3
+        });
+
+        let calls = parse_tool_calls(&chunk["message"]);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "shell");
+        assert_eq!(calls[0].arguments, r#"{"cmd":"ls"}"#);
+        assert!(calls[0].id.starts_with("call_"));
+        assert_eq!(parse_usage(&chunk), Some(Usage::new(12, 3)));
+
+        let sent = message_json(&ChatMessage {
+            tool_calls: calls,
+            ..ChatMessage::new(Role::Assistant, "")
+        });
+        assert_eq!(sent["tool_calls"][0]["function"]["arguments"]["cmd"], "ls");
+    }
+}
@@ -1,164 +1,312 @@
 // Copyright (c) 2025 SharPi Contributors
 // MIT License
 
-use crate::config;
-use crate::core::history;
-use anyhow::{Context, Result};
+use super::sse::SseReader;
+use super::{
+    interrupted, post_json, read_json, ChatMessage, ChatRequest, ChatResponse, ClientError, ClientResult, LlmClient,
+    RetryPolicy, ToolCall, Usage,
+};
+use crate::config::ClientConfig;
+use anyhow::Result;
 use serde_json::{json, Value};
+use std::io::BufReader;
 
-pub fn call_openai(input: &str, client_name: Option<&str>) -> Result<String> {
-    let config = config::load_config()?;
-    let client_config = config.get_client_config(client_name)?;
-
-    let base_url = if client_config.api_url.ends_with('/') {
-        client_config.api_url.clone()
-    } else {
-        format!("{}/", client_config.api_url)
-    };
+/// Client for OpenAI-compatible `/chat/completions` endpoints.
+pub struct OpenAiClient {
+    config: ClientConfig,
+}
 
-    let api_url = if base_url.ends_with("chat/completions/") {
-        base_url
-    } else {
-        format!("{}chat/completions", base_url)
-    };
+impl OpenAiClient {
+    pub fn new(config: ClientConfig) -> Self {
+        Self { config }
+    }
 
-    let request_body = format!(
-        r#"{{
-            "model": "{model}",
-            "messages": [
-                {{
-                    "role": "user",
-                    "content": "{content}"
-                }}
-            ],
-            "max_tokens": {max_tokens},
-            "temperature": {temperature}
-        }}"#,
-        model = client_config.model,
-        content = input.replace("\"", "\\\"").replace("\n", "\\n"),
-        max_tokens = client_config.max_tokens,
-        temperature = client_config.temperature
-    );
-
-    println!("Sending request to: {}", api_url);
-    println!("Request details:");
-    println!("  URL: {}", api_url);
-    println!("  Model: {}", client_config.model);
-
-    let response = match ureq::post(&api_url)
-        .set("Content-Type", "application/json")
-        .set("Authorization", &format!("Bearer {}", client_config.api_key))
-        .send_string(&request_body) {
-            Ok(res) => res,
-            Err(ureq::Error::Status(code, res)) => {
-                let error_body = res.into_string()
-                    .unwrap_or_else(|_| "Could not read error response".to_string());
-                return Err(anyhow::anyhow!(
-                    "API request failed with status {}: {}",
-                    code, error_body
-                ));
-            },
-            Err(err) => {
-                return Err(anyhow::anyhow!(
-                    "Network error while making API request: {}", err
-                ));
-            }
+    fn endpoint(&self) -> String {
+        let base_url = if self.config.api_url.ends_with('/') {
+            self.config.api_url.clone()
+        } else {
+            format!("{}/", self.config.api_url)
         };
 
-    let response_text = response.into_string()
-        .context("Failed to read response body")?;
+        if base_url.ends_with("chat/completions/") {
+            base_url
+        } else {
+            format!("{}chat/completions", base_url)
+        }
+    }
+
+    fn post(&self, body: &Value) -> ClientResult<ureq::Response> {
+        let request = ureq::post(&self.endpoint())
+            .set("Authorization", &format!("Bearer {}", self.config.api_key));
+        post_json(request, body, &RetryPolicy::from_config(&self.config))
+    }
+
+    fn request_body(&self, request: &ChatRequest) -> Value {
+        let messages: Vec<Value> = request.messages.iter().map(message_json).collect();
 
-    Ok(response_text)
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens,
+            "temperature": request.temperature
+        });
+
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.parameters
+                    }
+                }))
+                .collect();
+            body["tools"] = Value::Array(tools);
+        }
+
+        body
+    }
 }
 
-pub fn call_openai_with_history(input: &str, conversation_id: Option<&str>, client_name: Option<&str>) -> Result<String> {
-    let mut history = history::load_history()?;
+/// A message in the Chat Completions format, which is also the format of
+/// OpenAI fine-tuning data.
+pub fn message_json(msg: &ChatMessage) -> Value {
+    let mut message = json!({
+        "role": msg.role,
+        "content": msg.content
+    });
 
-    if let Some(id) = conversation_id {
-        if !history.set_active_conversation(id.to_string())? {
-            return Err(anyhow::anyhow!("Conversation with ID '{}' not found", id));
+    if !msg.tool_calls.is_empty() {
+        let calls: Vec<Value> = msg
+            .tool_calls
+            .iter()
+            .map(|call| json!({
+                "id": call.id,
+                "type": "function",
+                "function": {
+                    "name": call.name,
+                    "arguments": call.arguments
+                }
+            }))
+            .collect();
+        message["tool_calls"] = Value::Array(calls);
+        if msg.content.is_empty() {
+            message["content"] = Value::Null;
         }
     }
 
-    let (id, mut conversation) = history.ensure_active_conversation()?;
-    conversation.add_user_message(input.to_string());
+    if let Some(id) = &msg.tool_call_id {
+        message["tool_call_id"] = Value::String(id.clone());
+    }
 
-    let response = call_openai_with_conversation(&conversation, client_name)?;
+    message
+}
 
-    let parsed: Value = serde_json::from_str(&response)
-        .context("Failed to parse OpenAI response as JSON")?;
+fn parse_usage(usage: &Value) -> Option<Usage> {
+    let prompt_tokens = usage["prompt_tokens"].as_u64()?;
+    let completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0);
+    Some(Usage {
+        total_tokens: usage["total_tokens"].as_u64().unwrap_or(prompt_tokens + completion_tokens),
+        ..Usage::new(prompt_tokens, completion_tokens)
+    })
+}
 
-    let assistant_message = parsed["choices"][0]["message"]["content"]
-        .as_str()
-        .context("Could not find message content in API response")?
-        .to_string();
+fn parse_tool_calls(message: &Value) -> Vec<ToolCall> {
+    message["tool_calls"]
+        .as_array()
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|call| ToolCall {
+                    id: call["id"].as_str().unwrap_or_default().to_string(),
+                    name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: call["function"]["arguments"].as_str().unwrap_or_default().to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
 
-    conversation.add_assistant_message(assistant_message.clone());
+/// Merges streamed `delta.tool_calls` fragments, which arrive keyed by index
+/// with the ID and name first and the arguments spread over later chunks.
+fn merge_tool_call_deltas(tool_calls: &mut Vec<ToolCall>, deltas: &Value) {
+    let Some(deltas) = deltas.as_array() else {
+        return;
+    };
 
-    history::save_conversation(&id, &conversation)?;
-    history::save_history(&history)?;
+    for delta in deltas {
+        let index = delta["index"].as_u64().unwrap_or(0) as usize;
+        while tool_calls.len() <= index {
+            tool_calls.push(ToolCall {
+                id: String::new(),
+                name: String::new(),
+                arguments: String::new(),
+            });
+        }
 
-    Ok(assistant_message)
+        let call = &mut tool_calls[index];
+        if let Some(id) = delta["id"].as_str() {
+            call.id = id.to_string();
+        }
+        if let Some(name) = delta["function"]["name"].as_str() {
+            call.name.push_str(name);
+        }
+        if let Some(arguments) = delta["function"]["arguments"].as_str() {
+            call.arguments.push_str(arguments);
+        }
+    }
 }
 
-fn call_openai_with_conversation(conversation: &history::Conversation, client_name: Option<&str>) -> Result<String> {
-    let config = config::load_config()?;
-    let client_config = config.get_client_config(client_name)?;
+impl LlmClient for OpenAiClient {
+    fn provider(&self) -> &str {
+        "openai"
+    }
 
-    let base_url = if client_config.api_url.ends_with('/') {
-        client_config.api_url.clone()
-    } else {
-        format!("{}/", client_config.api_url)
-    };
+    fn chat(&self, request: &ChatRequest) -> ClientResult<ChatResponse> {
+        let response = self.post(&self.request_body(request))?;
 
-    let api_url = if base_url.ends_with("chat/completions/") {
-        base_url
-    } else {
-        format!("{}chat/completions", base_url)
-    };
+        let parsed = read_json(response)?;
+
+        let message = &parsed["choices"][0]["message"];
+        if !message.is_object() {
+            return Err(ClientError::InvalidResponse("Could not find message in API response".to_string()));
+        }
+
+        // Content is null when the model only requests tool calls.
+        let content = message["content"].as_str().unwrap_or_default().to_string();
+        let tool_calls = parse_tool_calls(message);
+
+        let model = parsed["model"]
+            .as_str()
+            .unwrap_or(&request.model)
+            .to_string();
 
-    let mut messages = Vec::new();
-    for msg in &conversation.messages {
-        messages.push(json!({
-            "role": msg.role,
-            "content": msg.content
-        }));
+        let usage = parse_usage(&parsed["usage"]);
+
+        Ok(ChatResponse {
+            content,
+            model,
+            tool_calls,
+            usage,
+            raw: parsed,
+        })
     }
 
-    let request_body = json!({
-        "model": client_config.model,
-        "messages": messages,
-        "max_tokens": client_config.max_tokens,
-        "temperature": client_config.temperature
-    });
+    fn chat_stream(&self, request: &ChatRequest, on_delta: &mut dyn FnMut(&str)) -> ClientResult<ChatResponse> {
+        let mut body = self.request_body(request);
+        body["stream"] = Value::Bool(true);
+        body["stream_options"] = json!({"include_usage": true});
+
+        let response = self.post(&body)?;
+        let events = SseReader::new(BufReader::new(response.into_reader()));
+
+        let mut content = String::new();
+        let mut model = request.model.clone();
+        let mut tool_calls = Vec::new();
+        let mut usage = None;
+        let mut finished = false;
+
+        for event in events {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => return Err(interrupted(content, err.to_string())),
+            };
+
+            if event.data == "[DONE]" {
+                finished = true;
+                break;
+            }
+
+            let chunk: Value = match serde_json::from_str(&event.data) {
+                Ok(chunk) => chunk,
+                Err(err) => return Err(interrupted(content, format!("malformed chunk: {}", err))),
+            };
+
+            if let Some(message) = chunk["error"]["message"].as_str() {
+                return Err(interrupted(content, message.to_string()));
+            }
+
+            if let Some(name) = chunk["model"].as_str() {
+                model = name.to_string();
+            }
+
+            // With `include_usage`, the final chunk has no choices, only usage.
+            if let Some(chunk_usage) = parse_usage(&chunk["usage"]) {
+                usage = Some(chunk_usage);
+            }
 
-    println!("Sending request with conversation history to: {}", api_url);
-    println!("  URL: {}", api_url);
-    println!("  Model: {}", client_config.model);
-    println!("  Message count: {}", messages.len());
-
-    let response = match ureq::post(&api_url)
-        .set("Content-Type", "application/json")
-        .set("Authorization", &format!("Bearer {}", client_config.api_key))
-        .send_string(&request_body.to_string()) {
-            Ok(res) => res,
-            Err(ureq::Error::Status(code, res)) => {
-                let error_body = res.into_string()
-                    .unwrap_or_else(|_| "Could not read error response".to_string());
-                return Err(anyhow::anyhow!(
-                    "API request failed with status {}: {}",
-                    code, error_body
-                ));
-            },
-            Err(err) => {
-                return Err(anyhow::anyhow!(
-                    "Network error while making API request: {}", err
-                ));
+            let delta = &chunk["choices"][0]["delta"];
+            if let Some(text) = delta["content"].as_str() {
+                on_delta(text);
+                content.push_str(text);
             }
+            merge_tool_call_deltas(&mut tool_calls, &delta["tool_calls"]);
+
+            if chunk["choices"][0]["finish_reason"].is_string() {
+                finished = true;
+            }
+        }
+
+        if !finished {
+            return Err(interrupted(content, "stream ended before completion".to_string()));
+        }
+
+        Ok(ChatResponse {
+            content,
+            model,
+            tool_calls,
+            usage,
+            raw: Value::Null,
+        })
+    }
+}
+
+pub fn call_openai(input: &str, client_name: Option<&str>) -> Result<String> {
+    Ok(super::call(input, client_name)?.content)
+}
+
+pub fn call_openai_with_history(input: &str, conversation_id: Option<&str>, client_name: Option<&str>) -> Result<String> {
+    Ok(super::call_with_history(input, conversation_id, client_name)?.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::history::Role;
+
+    #[test]
+    fn test_tool_messages_round_trip() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "shell".to_string(),
+            arguments: "{\"cmd\":\"ls\"}".to_string(),
+        };
+        let assistant = ChatMessage {
+            tool_calls: vec![call.clone()],
+            ..ChatMessage::new(Role::Assistant, "")
         };
 
-    let response_text = response.into_string()
-        .context("Failed to read response body")?;
+        let json = message_json(&assistant);
+        assert!(json["content"].is_null());
+        assert_eq!(parse_tool_calls(&json), vec![call]);
 
-    Ok(response_text)
+        let result = message_json(&ChatMessage::tool_result("call_1", "a.txt"));
+        assert_eq!(result["role"], "tool");
+        assert_eq!(result["tool_call_id"], "call_1");
+    }
+
+    #[test]
+    fn test_merges_streamed_tool_call_fragments() {
+        let mut calls = Vec::new();
+        merge_tool_call_deltas(&mut calls, &json!([{"index": 0, "id": "call_1", "function": {"name": "shell", "arguments": ""}}]));
+        merge_tool_call_deltas(&mut calls, &json!([{"index": 0, "function": {"arguments": "{\"cmd\""}}]));
+        merge_tool_call_deltas(&mut calls, &json!([{"index": 0, "function": {"arguments": ":\"ls\"}"}}]));
+
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].arguments, "{\"cmd\":\"ls\"}");
+    }
 }
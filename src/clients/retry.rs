@@ -0,0 +1,274 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use super::{ClientError, ClientResult};
+use crate::config::ClientConfig;
+use chrono::{DateTime, Utc};
+use log::warn;
+use rand::Rng;
+use std::thread;
+use std::time::Duration;
+
+/// Upper bound for any single wait, including server-requested ones.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often and how long to wait before re-sending a failed request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// Fraction of each backoff delay to randomise by, from 0.0 to 1.0.
+    pub jitter: f32,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &ClientConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            jitter: config.retry_jitter.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::ZERO,
+            jitter: 0.0,
+        }
+    }
+
+    /// The wait before retry number `attempt` (starting at 0).
+    ///
+    /// A delay requested by the server wins; otherwise the base delay is
+    /// doubled per attempt and randomised by `jitter`.
+    pub fn delay_for(&self, attempt: u32, err: &ClientError) -> Duration {
+        if let Some(retry_after) = err.retry_after() {
+            return retry_after.min(MAX_DELAY);
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_DELAY);
+
+        if self.jitter > 0.0 {
+            let factor = rand::thread_rng().gen_range(1.0 - self.jitter..=1.0 + self.jitter);
+            backoff.mul_f32(factor)
+        } else {
+            backoff
+        }
+    }
+
+    /// Runs `op`, retrying retryable failures until it succeeds, fails with
+    /// a non-retryable error or the retries are used up.
+    pub fn run<T>(&self, mut op: impl FnMut() -> ClientResult<T>) -> ClientResult<T> {
+        let mut attempt = 0;
+
+        loop {
+            match op() {
+                Err(err) if err.is_retryable() && attempt < self.max_retries => {
+                    let delay = self.delay_for(attempt, &err);
+                    warn!("{}; retrying in {:.1}s ({}/{})", err, delay.as_secs_f32(), attempt + 1, self.max_retries);
+                    thread::sleep(delay);
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Reads how long the server asked us to wait from a response's headers.
+///
+/// Understands `retry-after-ms`, `retry-after` (seconds or an HTTP date) and
+/// OpenAI's `x-ratelimit-reset-requests` / `x-ratelimit-reset-tokens`
+/// durations such as "1s" or "6m0s". When several are present the longest
+/// wait is used.
+pub fn retry_after(response: &ureq::Response) -> Option<Duration> {
+    let mut delays = Vec::new();
+
+    if let Some(ms) = response.header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        delays.extend(seconds(ms / 1000.0));
+    }
+
+    if let Some(value) = response.header("retry-after") {
+        delays.extend(parse_retry_after(value, Utc::now()));
+    }
+
+    for name in ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"] {
+        if let Some(value) = response.header(name) {
+            delays.extend(parse_reset_duration(value));
+        }
+    }
+
+    delays.into_iter().max()
+}
+
+/// A wait of `value` seconds, capped at `MAX_DELAY`, or `None` if it is
+/// negative or not a number. The value comes from the server, so it must
+/// not be trusted to fit in a `Duration`.
+fn seconds(value: f64) -> Option<Duration> {
+    if !value.is_finite() || value < 0.0 {
+        return None;
+    }
+    Duration::try_from_secs_f64(value.min(MAX_DELAY.as_secs_f64())).ok()
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(value) = value.parse::<f64>() {
+        return seconds(value);
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Parses Go-style durations like "20ms", "1.5s" or "6m0s".
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let seconds_per_unit = match &rest[..unit_len] {
+            "ms" => 0.001,
+            "s" | "" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        total += number * seconds_per_unit;
+    }
+
+    seconds(total)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clients::openai::OpenAiClient;
+    use crate::clients::{ChatMessage, ChatRequest, LlmClient};
+    use crate::core::history::Role;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serves `responses` in order, one per connection, and counts requests.
+    pub(crate) fn mock_server(responses: Vec<String>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut content_length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (url, hits)
+    }
+
+    pub(crate) fn http_response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            status, body.len(), headers, body
+        )
+    }
+
+    fn client_for(url: &str, max_retries: u32) -> (OpenAiClient, ChatRequest) {
+        let config: ClientConfig = toml::from_str(&format!(
+            "api_key = \"key\"\napi_url = \"{}\"\nmodel = \"m\"\nmax_retries = {}\nretry_base_delay_ms = 1",
+            url, max_retries
+        ))
+        .unwrap();
+        let request = ChatRequest::new(&config, vec![ChatMessage::new(Role::User, "hi")]);
+        (OpenAiClient::new(config), request)
+    }
+
+    #[test]
+    fn test_retries_rate_limits_and_server_errors() {
+        let ok = r#"{"model": "m", "choices": [{"message": {"content": "done"}}]}"#;
+        let (url, hits) = mock_server(vec![
+            http_response("429 Too Many Requests", "Retry-After: 0\r\n", r#"{"error": {"message": "slow down"}}"#),
+            http_response("503 Service Unavailable", "", "{}"),
+            http_response("200 OK", "", ok),
+        ]);
+
+        let (client, request) = client_for(&url, 2);
+        assert_eq!(client.chat(&request).unwrap().content, "done");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_retries_and_skips_client_errors() {
+        let (url, hits) = mock_server(vec![
+            http_response("500 Internal Server Error", "", "{}"),
+            http_response("500 Internal Server Error", "", "{}"),
+        ]);
+        let (client, request) = client_for(&url, 1);
+        assert!(matches!(client.chat(&request), Err(ClientError::Server(_))));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let (url, hits) = mock_server(vec![http_response("401 Unauthorized", "", "{}")]);
+        let (client, request) = client_for(&url, 3);
+        assert!(matches!(client.chat(&request), Err(ClientError::Authentication(_))));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_parses_retry_after_formats() {
+        let now = Utc::now();
+        assert_eq!(parse_retry_after("3", now), Some(Duration::from_secs(3)));
+        let date = (now + chrono::Duration::seconds(10)).to_rfc2822();
+        let delay = parse_retry_after(&date, now).unwrap();
+        assert!(delay > Duration::from_secs(8) && delay <= Duration::from_secs(10));
+
+        assert_eq!(parse_reset_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset_duration("0m45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(MAX_DELAY));
+        assert_eq!(parse_reset_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset_duration("soon"), None);
+
+        // Values the server controls must not panic, however odd.
+        assert_eq!(parse_retry_after("inf", now), None);
+        assert_eq!(parse_retry_after("NaN", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+        assert_eq!(parse_retry_after("1e20", now), Some(MAX_DELAY));
+        assert_eq!(parse_reset_duration("99999999999999999999h"), Some(MAX_DELAY));
+        assert_eq!(parse_reset_duration("1e400s"), None);
+    }
+}
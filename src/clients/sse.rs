@@ -0,0 +1,102 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use std::io::{self, BufRead};
+
+/// A single server-sent event.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SseEvent {
+    /// The `event:` field, if the server named the event.
+    pub event: Option<String>,
+    /// All `data:` lines of the event, joined with newlines.
+    pub data: String,
+}
+
+/// Reads server-sent events from a line-oriented stream.
+///
+/// Events are dispatched on blank lines. Comment lines (starting with `:`)
+/// and fields other than `event` and `data` are ignored. A trailing event
+/// that is not followed by a blank line is still returned at end of stream.
+pub struct SseReader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> SseReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: BufRead> Iterator for SseReader<R> {
+    type Item = io::Result<SseEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = SseEvent::default();
+        let mut has_data = false;
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => {
+                    return if has_data || event.event.is_some() {
+                        Some(Ok(event))
+                    } else {
+                        None
+                    };
+                },
+                Ok(_) => {},
+                Err(err) => return Some(Err(err)),
+            }
+
+            let trimmed = line.trim_end_matches(['\r', '\n']);
+
+            if trimmed.is_empty() {
+                if has_data || event.event.is_some() {
+                    return Some(Ok(event));
+                }
+                continue;
+            }
+
+            if trimmed.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match trimmed.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (trimmed, ""),
+            };
+
+            match field {
+                "event" => event.event = Some(value.to_string()),
+                "data" => {
+                    if has_data {
+                        event.data.push('\n');
+                    }
+                    event.data.push_str(value);
+                    has_data = true;
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_events_and_skips_comments() {
+        let input = ": keep-alive\n\nevent: delta\ndata: {\"a\":1}\n\ndata: one\ndata: two\n\ndata: [DONE]";
+        let events: Vec<SseEvent> = SseReader::new(input.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].event.as_deref(), Some("delta"));
+        assert_eq!(events[0].data, "{\"a\":1}");
+        assert_eq!(events[1].data, "one\ntwo");
+        assert_eq!(events[2].data, "[DONE]");
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright (c) 2025 SharPi Contributors
 // MIT License
 
+use crate::clients::{ToolDefinition, Usage};
 use anyhow::{Context, Result};
 use serde::Deserialize;
 use serde_json::Value;
@@ -10,6 +11,11 @@ use std::path::PathBuf;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ClientConfig {
+    /// Backend implementation for this entry; see `clients::create_client`.
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// May be left out for local servers that need no authentication.
+    #[serde(default)]
     pub api_key: String,
     pub api_url: String,
     pub model: String,
@@ -17,6 +23,25 @@ pub struct ClientConfig {
     pub max_tokens: u32,
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    /// Retries after rate limits, server errors and network failures.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// Fraction by which each backoff delay is randomised.
+    #[serde(default = "default_retry_jitter")]
+    pub retry_jitter: f32,
+    /// Overrides the built-in context window size for `model`.
+    #[serde(default)]
+    pub context_window: Option<u32>,
+    /// Summarize old turns with the model once a conversation outgrows the
+    /// context window, instead of only leaving them out of requests.
+    #[serde(default)]
+    pub summarize_history: bool,
+    /// Ask the model to title implicitly created conversations after the
+    /// first exchange, rather than naming them after the first message.
+    #[serde(default = "default_auto_title")]
+    pub auto_title: bool,
 }
 
 fn default_max_tokens() -> u32 {
@@ -27,6 +52,22 @@ fn default_temperature() -> f32 {
     0.7
 }
 
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_retry_jitter() -> f32 {
+    0.2
+}
+
+fn default_auto_title() -> bool {
+    true
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct ClientsConfig {
     pub default: String,
@@ -34,6 +75,75 @@ pub struct ClientsConfig {
     pub providers: HashMap<String, ClientConfig>,
 }
 
+/// Price of a model in currency units per million tokens.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// The `[history]` section: where conversations are stored, and how long
+/// `spi chat gc` keeps them.
+#[derive(Deserialize, Debug, Clone)]
+pub struct HistoryConfig {
+    /// "json" (one file per conversation) or "sqlite" (`history.db`, with
+    /// full-text search).
+    #[serde(default = "default_history_backend")]
+    pub backend: String,
+    /// Expire conversations not updated for this many days.
+    #[serde(default)]
+    pub max_age_days: Option<u32>,
+    /// Expire the least recently updated conversations beyond this many.
+    #[serde(default)]
+    pub max_conversations: Option<usize>,
+    /// Expire the least recently updated conversations once the total size
+    /// of stored conversations exceeds this.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// What happens to expired conversations: "archive" or "delete".
+    #[serde(default = "default_expire")]
+    pub expire: String,
+    /// Where the key for encrypting conversations comes from: "none",
+    /// "keyring", "passphrase" or "key_file". See `core::crypto`.
+    #[serde(default = "default_encryption")]
+    pub encryption: String,
+    /// The key file used with `encryption = "key_file"`.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            backend: default_history_backend(),
+            max_age_days: None,
+            max_conversations: None,
+            max_bytes: None,
+            expire: default_expire(),
+            encryption: default_encryption(),
+            key_file: None,
+        }
+    }
+}
+
+fn default_history_backend() -> String {
+    "json".to_string()
+}
+
+fn default_expire() -> String {
+    "archive".to_string()
+}
+
+fn default_encryption() -> String {
+    "none".to_string()
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub clients: ClientsConfig,
@@ -41,9 +151,53 @@ pub struct Config {
     pub tools: HashMap<String, Value>,
     #[serde(default)]
     pub commands: HashMap<String, Value>,
+    /// Prices keyed by model name, e.g. `[pricing."gpt-4o"]`.
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPricing>,
+    #[serde(default)]
+    pub history: HistoryConfig,
 }
 
 impl Config {
+    /// Pricing for `model`, falling back to the longest configured name that
+    /// prefixes it, so "gpt-4o" also prices "gpt-4o-2024-08-06".
+    pub fn pricing_for(&self, model: &str) -> Option<&ModelPricing> {
+        self.pricing.get(model).or_else(|| {
+            self.pricing
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, pricing)| pricing)
+        })
+    }
+
+    /// Tools offered to the model: every `[tools.<name>]` entry that has a
+    /// `parameters` JSON Schema, plus an optional `description`.
+    pub fn tool_definitions(&self) -> Result<Vec<ToolDefinition>> {
+        let mut names: Vec<&String> = self.tools.keys().collect();
+        names.sort();
+
+        let mut definitions = Vec::new();
+        for name in names {
+            let tool = &self.tools[name];
+            let Some(parameters) = tool.get("parameters") else {
+                continue;
+            };
+
+            if !parameters.is_object() {
+                return Err(anyhow::anyhow!("'parameters' of tool '{}' must be a table", name));
+            }
+
+            definitions.push(ToolDefinition {
+                name: name.clone(),
+                description: tool["description"].as_str().unwrap_or_default().to_string(),
+                parameters: parameters.clone(),
+            });
+        }
+
+        Ok(definitions)
+    }
+
     pub fn get_client_config(&self, client_name: Option<&str>) -> Result<&ClientConfig> {
         let client_name = client_name.unwrap_or(&self.clients.default);
         self.clients
@@ -77,6 +231,16 @@ pub fn load_config() -> Result<Config> {
     Ok(config)
 }
 
+/// The `[history]` section, or its defaults if there is no config file yet,
+/// so that history commands work before `spi init`.
+pub fn load_history_config() -> Result<HistoryConfig> {
+    if !get_config_path().exists() {
+        return Ok(HistoryConfig::default());
+    }
+
+    Ok(load_config()?.history)
+}
+
 pub fn create_default_config(force: bool) -> Result<()> {
     let config_path = get_config_path();
 
@@ -99,13 +263,44 @@ default = "openai"
 [clients.openai]
 api_key = "your-api-key-here"
 api_url = "https://api.openai.com/v1"
-model = "claude-3-7-sonnet"
+model = "gpt-4-turbo"
 max_tokens = 1000
 temperature = 0.7
 
+[clients.claude]
+provider = "anthropic"
+api_key = "your-api-key-here"
+api_url = "https://api.anthropic.com/v1"
+model = "claude-3-7-sonnet-latest"
+max_tokens = 1000
+temperature = 0.7
+
+# Local models served by Ollama; no API key needed.
+[clients.ollama]
+provider = "ollama"
+api_url = "http://localhost:11434"
+model = "llama3.1"
+
 [tools]
 
 [commands]
+
+# "json" keeps one file per conversation; "sqlite" keeps them in
+# history.db and enables 'spi chat search'.
+[history]
+backend = "json"
+# Limits applied by 'spi chat gc', which archives (or, with
+# expire = "delete", deletes) the oldest conversations beyond them.
+# Pinned conversations and the active one are never touched.
+# max_age_days = 90
+# max_conversations = 200
+# max_bytes = 50000000
+expire = "archive"
+# Encrypt conversation files with a key from the OS keyring ("keyring"),
+# a passphrase ("passphrase", read from SHARPI_PASSPHRASE or prompted for)
+# or a file ("key_file", with key_file = "<path>"). Run
+# 'spi chat encrypt' after enabling it to encrypt existing conversations.
+encryption = "none"
 "#;
 
     fs::write(&config_path, default_config)
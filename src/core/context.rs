@@ -0,0 +1,323 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::clients::{ChatMessage, ChatRequest, LlmClient};
+use crate::config::ClientConfig;
+use crate::core::history::{Conversation, ConversationSummary, Role, UsageEntry};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::info;
+
+/// Rough per-message overhead for role markers and separators.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Context windows of well-known model families, matched by name prefix.
+/// More specific prefixes come first.
+const CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_000_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("llama3.1", 128_000),
+    ("llama3.2", 128_000),
+    ("llama3", 8_192),
+    ("mistral", 32_768),
+    ("qwen2.5", 32_768),
+];
+
+const DEFAULT_CONTEXT_WINDOW: u32 = 8_192;
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below so it can replace the original \
+messages as context for continuing it. Keep decisions, facts, code identifiers, open questions \
+and anything the user asked to remember. Reply with the summary only.";
+
+/// Estimates the tokens in `text` at about four characters per token, which
+/// errs on the high side for English prose and code.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub fn estimate_message_tokens(message: &ChatMessage) -> usize {
+    let tool_calls: usize = message
+        .tool_calls
+        .iter()
+        .map(|call| estimate_tokens(&call.name) + estimate_tokens(&call.arguments))
+        .sum();
+
+    MESSAGE_OVERHEAD + estimate_tokens(&message.content) + tool_calls
+}
+
+pub fn estimate_request_tokens(request: &ChatRequest) -> usize {
+    let tools: usize = request
+        .tools
+        .iter()
+        .map(|tool| estimate_tokens(&tool.name) + estimate_tokens(&tool.description) + estimate_tokens(&tool.parameters.to_string()))
+        .sum();
+
+    request.messages.iter().map(estimate_message_tokens).sum::<usize>() + tools
+}
+
+/// The context window for `model`: the client's `context_window` setting if
+/// given, otherwise a built-in figure for the model family.
+pub fn context_window(client_config: &ClientConfig, model: &str) -> u32 {
+    client_config.context_window.unwrap_or_else(|| {
+        CONTEXT_WINDOWS
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, window)| *window)
+            .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+    })
+}
+
+/// Keeps requests within a model's context window.
+pub struct ContextManager {
+    /// Tokens available for the prompt, after reserving room for the reply.
+    pub budget: usize,
+}
+
+impl ContextManager {
+    pub fn new(client_config: &ClientConfig, request: &ChatRequest) -> Self {
+        let window = context_window(client_config, &request.model) as usize;
+        Self {
+            budget: window.saturating_sub(request.max_tokens as usize),
+        }
+    }
+
+    pub fn fits(&self, request: &ChatRequest) -> bool {
+        estimate_request_tokens(request) <= self.budget
+    }
+
+    /// Drops the oldest messages until the request fits, and returns how
+    /// many were dropped.
+    ///
+    /// System messages at the start are always kept, as is the latest turn:
+    /// the last user message and any tool calls and results after it. Whole
+    /// turns are dropped, so the kept history starts at a user message and
+    /// tool results are never separated from the call that produced them.
+    pub fn truncate(&self, request: &mut ChatRequest) -> usize {
+        let pinned = request.messages.iter().take_while(|msg| msg.role == Role::System).count();
+        let mut keep = request.messages[pinned..]
+            .iter()
+            .rposition(|msg| msg.role == Role::User)
+            .map_or(pinned, |index| pinned + index);
+        let mut dropped = 0;
+
+        while !self.fits(request) && keep > pinned {
+            request.messages.remove(pinned);
+            keep -= 1;
+            dropped += 1;
+
+            while keep > pinned && request.messages[pinned].role != Role::User {
+                request.messages.remove(pinned);
+                keep -= 1;
+                dropped += 1;
+            }
+        }
+
+        dropped
+    }
+
+    /// Index of the first message to keep verbatim when summarizing: the
+    /// oldest user message from which the rest of the conversation takes up
+    /// no more than half the budget.
+    fn summary_split(&self, conversation: &Conversation) -> usize {
+        let mut used = 0;
+        let mut split = conversation.messages.len();
+
+        for (index, message) in conversation.messages.iter().enumerate().rev() {
+            used += MESSAGE_OVERHEAD + estimate_tokens(&message.content);
+            if used > self.budget / 2 {
+                break;
+            }
+            if message.role == Role::User {
+                split = index;
+            }
+        }
+
+        // The latest user message is the one being answered; never fold it in.
+        let last_user = conversation
+            .messages
+            .iter()
+            .rposition(|message| message.role == Role::User)
+            .unwrap_or(conversation.messages.len());
+
+        split.min(last_user)
+    }
+
+    /// Replaces the older part of the conversation with a model-written
+    /// summary, stored on the conversation. Returns false if there was
+    /// nothing new to summarize.
+    ///
+    /// Normally the recent turns filling half the budget are kept verbatim;
+    /// with `everything_but_last` only the latest user turn is.
+    pub fn summarize(
+        &self,
+        client: &dyn LlmClient,
+        client_config: &ClientConfig,
+        conversation: &mut Conversation,
+        everything_but_last: bool,
+    ) -> Result<bool> {
+        let start = conversation.summary.as_ref().map_or(0, |summary| summary.covers);
+        let split = if everything_but_last {
+            conversation.messages.iter().rposition(|message| message.role == Role::User).unwrap_or(0)
+        } else {
+            self.summary_split(conversation)
+        };
+        let split = split.max(start);
+
+        if split == start {
+            return Ok(false);
+        }
+
+        let mut transcript = String::new();
+        if let Some(summary) = &conversation.summary {
+            transcript.push_str(&format!("Summary of earlier messages:\n{}\n\n", summary.content));
+        }
+        for message in &conversation.messages[start..split] {
+            transcript.push_str(&format!("{}: {}\n\n", message.role, message.content));
+        }
+
+        // The oldest material goes first if even the transcript is too long.
+        let limit = self.budget.saturating_sub(estimate_tokens(SUMMARY_PROMPT) + 2 * MESSAGE_OVERHEAD) * 4;
+        let skip = transcript.chars().count().saturating_sub(limit);
+        let transcript: String = transcript.chars().skip(skip).collect();
+
+        let request = ChatRequest::new(client_config, vec![
+            ChatMessage::new(Role::System, SUMMARY_PROMPT),
+            ChatMessage::new(Role::User, &transcript),
+        ]);
+        let response = client.chat(&request).context("Failed to summarize conversation")?;
+
+        info!("Summarized {} messages into {} characters", split - start, response.content.len());
+
+        if let Some(usage) = response.usage {
+            conversation.usage += usage;
+        }
+        // The summary being replaced was paid for too; keep its usage.
+        if let Some(ConversationSummary { usage: Some(usage), model, created_at, .. }) = conversation.summary.take() {
+            conversation.other_usage.push(UsageEntry { usage, model, timestamp: created_at });
+        }
+        conversation.summary = Some(ConversationSummary {
+            content: response.content,
+            covers: split,
+            created_at: Utc::now(),
+            usage: response.usage,
+            model: Some(response.model),
+        });
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: &str) -> ClientConfig {
+        toml::from_str(&format!(
+            "api_url = \"http://localhost\"\nmodel = \"test-model\"\nmax_tokens = 100\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_context_window_lookup() {
+        assert_eq!(context_window(&config(""), "gpt-4o-mini"), 128_000);
+        assert_eq!(context_window(&config(""), "gpt-4-0613"), 8_192);
+        assert_eq!(context_window(&config(""), "something-else"), DEFAULT_CONTEXT_WINDOW);
+        assert_eq!(context_window(&config("context_window = 500"), "gpt-4o"), 500);
+    }
+
+    #[test]
+    fn test_truncate_keeps_system_prompt_and_latest_turns() {
+        let config = config("context_window = 200");
+        let long = "x".repeat(160);
+        let mut request = ChatRequest::new(&config, vec![
+            ChatMessage::new(Role::System, "Be terse."),
+            ChatMessage::new(Role::User, &long),
+            ChatMessage::new(Role::Assistant, &long),
+            ChatMessage::tool_result("call_1", &long),
+            ChatMessage::new(Role::User, "latest question"),
+        ]);
+
+        let manager = ContextManager::new(&config, &request);
+        assert_eq!(manager.budget, 100);
+        assert!(!manager.fits(&request));
+
+        let dropped = manager.truncate(&mut request);
+        assert_eq!(dropped, 3);
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, Role::System);
+        assert_eq!(request.messages[1].content, "latest question");
+        assert!(manager.fits(&request));
+    }
+
+    #[test]
+    fn test_truncate_keeps_tool_results_with_their_call() {
+        let config = config("context_window = 200");
+        let mut call = ChatMessage::new(Role::Assistant, "");
+        call.tool_calls.push(crate::clients::ToolCall {
+            id: "call_1".to_string(),
+            name: "shell".to_string(),
+            arguments: "{}".to_string(),
+        });
+        let mut request = ChatRequest::new(&config, vec![
+            ChatMessage::new(Role::User, &"x".repeat(400)),
+            ChatMessage::new(Role::Assistant, &"y".repeat(400)),
+            ChatMessage::new(Role::User, "run it"),
+            call,
+            ChatMessage::tool_result("call_1", "ok"),
+        ]);
+
+        let manager = ContextManager::new(&config, &request);
+        assert_eq!(manager.truncate(&mut request), 2);
+        let roles: Vec<Role> = request.messages.iter().map(|msg| msg.role).collect();
+        assert_eq!(roles, [Role::User, Role::Assistant, Role::Tool]);
+    }
+
+    #[test]
+    fn test_summarizing_again_keeps_earlier_summary_usage() {
+        use crate::clients::{ChatResponse, ClientResult, Usage};
+        use serde_json::Value;
+
+        struct Summarizer;
+        impl LlmClient for Summarizer {
+            fn provider(&self) -> &str {
+                "test"
+            }
+
+            fn chat(&self, _request: &ChatRequest) -> ClientResult<ChatResponse> {
+                Ok(ChatResponse {
+                    content: "summary".to_string(),
+                    model: "m".to_string(),
+                    tool_calls: Vec::new(),
+                    usage: Some(Usage::new(10, 2)),
+                    raw: Value::Null,
+                })
+            }
+        }
+
+        let config = config("context_window = 1000");
+        let (_, mut conversation) = Conversation::new("Long".to_string());
+        for turn in 0..3 {
+            conversation.add_user_message(format!("question {}", turn));
+            conversation.add_assistant_message("answer".to_string());
+        }
+        let manager = ContextManager::new(&config, &ChatRequest::new(&config, Vec::new()));
+
+        assert!(manager.summarize(&Summarizer, &config, &mut conversation, true).unwrap());
+        conversation.add_user_message("one more".to_string());
+        assert!(manager.summarize(&Summarizer, &config, &mut conversation, true).unwrap());
+
+        assert_eq!(conversation.other_usage.len(), 1);
+        assert_eq!(conversation.other_usage[0].usage, Usage::new(10, 2));
+        assert_eq!(conversation.usage.total_tokens, 24);
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::config::HistoryConfig;
+use anyhow::{anyhow, Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use std::fmt;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+
+/// Start of every encrypted file, followed by the nonce and the ciphertext.
+/// The version digit allows the format to change later.
+const MAGIC: &[u8] = b"SPENC1";
+
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+const SALT_LENGTH: usize = 16;
+
+const KEYRING_SERVICE: &str = "sharpi";
+const KEYRING_USER: &str = "history-key";
+
+/// Read instead of prompting, for scripts and editor plugins.
+pub const PASSPHRASE_ENV: &str = "SHARPI_PASSPHRASE";
+
+/// Random salt for deriving a key from a passphrase, kept next to the
+/// conversations it protects.
+const SALT_FILE: &str = "encryption.salt";
+
+/// Known text encrypted with the key when it is first used, so that a
+/// different key (such as a mistyped passphrase) is refused before it is
+/// used to write anything.
+const CHECK_FILE: &str = "encryption.check";
+const CHECK_TEXT: &[u8] = b"sharpi history key";
+
+/// Encrypts and decrypts stored conversations with XChaCha20-Poly1305.
+///
+/// Each file gets a fresh random nonce, and the authentication tag means a
+/// wrong key or a damaged file is reported rather than read as garbage.
+#[derive(Clone)]
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cipher { .. }")
+    }
+}
+
+/// Whether `data` was written by `Cipher::encrypt`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; KEY_LENGTH]) -> Self {
+        Self {
+            aead: XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Derives the key from a passphrase with Argon2id.
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut key = [0u8; KEY_LENGTH];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| anyhow!("Failed to derive a key from the passphrase: {}", err))?;
+        Ok(Self::new(&key))
+    }
+
+    /// Reads a key file holding 32 bytes, either raw or as 64 hex digits,
+    /// such as one made by `head -c 32 /dev/urandom > key`.
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let content = fs::read(path).context(format!("Failed to read key file: {}", path.display()))?;
+
+        let key = match <[u8; KEY_LENGTH]>::try_from(content.as_slice()) {
+            Ok(key) => key,
+            Err(_) => std::str::from_utf8(&content)
+                .ok()
+                .and_then(|text| decode_hex(text.trim()))
+                .ok_or_else(|| anyhow!("Key file {} must hold 32 bytes or 64 hex digits", path.display()))?,
+        };
+
+        Ok(Self::new(&key))
+    }
+
+    /// Uses the key kept in the OS keyring, creating one the first time.
+    pub fn from_keyring() -> Result<Self> {
+        let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).context("Failed to open the OS keyring")?;
+
+        let key = match entry.get_password() {
+            Ok(hex) => decode_hex(&hex).ok_or_else(|| anyhow!("The history key in the OS keyring is malformed"))?,
+            Err(keyring::Error::NoEntry) => {
+                let mut key = [0u8; KEY_LENGTH];
+                rand::thread_rng().fill_bytes(&mut key);
+                entry
+                    .set_password(&encode_hex(&key))
+                    .context("Failed to store a new history key in the OS keyring")?;
+                key
+            },
+            Err(err) => return Err(anyhow::Error::new(err).context("Failed to read the history key from the OS keyring")),
+        };
+
+        Ok(Self::new(&key))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .aead
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .expect("encrypting into a Vec cannot fail");
+
+        [MAGIC, &nonce, &ciphertext].concat()
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let rest = data.strip_prefix(MAGIC).ok_or_else(|| anyhow!("Data is not encrypted"))?;
+        if rest.len() < NONCE_LENGTH {
+            return Err(anyhow!("Encrypted data is truncated"));
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt; the key is wrong or the data is damaged"))
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<[u8; KEY_LENGTH]> {
+    if text.len() != KEY_LENGTH * 2 || !text.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0u8; KEY_LENGTH];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// The salt in `<root>/encryption.salt`, created if there is none yet.
+fn load_salt(root: &Path) -> Result<Vec<u8>> {
+    let path = root.join(SALT_FILE);
+    if path.exists() {
+        return fs::read(&path).context(format!("Failed to read {}", path.display()));
+    }
+
+    let mut salt = vec![0u8; SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    fs::create_dir_all(root).context(format!("Failed to create directory: {}", root.display()))?;
+    fs::write(&path, &salt).context(format!("Failed to write {}", path.display()))?;
+    Ok(salt)
+}
+
+/// Fails if `cipher` is not the key the store in `root` was encrypted
+/// with; the first key used is recorded.
+fn verify_key(cipher: &Cipher, root: &Path) -> Result<()> {
+    let path = root.join(CHECK_FILE);
+
+    if !path.exists() {
+        fs::create_dir_all(root).context(format!("Failed to create directory: {}", root.display()))?;
+        return fs::write(&path, cipher.encrypt(CHECK_TEXT)).context(format!("Failed to write {}", path.display()));
+    }
+
+    let check = fs::read(&path).context(format!("Failed to read {}", path.display()))?;
+    match cipher.decrypt(&check) {
+        Ok(text) if text == CHECK_TEXT => Ok(()),
+        _ => Err(anyhow!("Wrong key: conversations in {} were encrypted with a different one", root.display())),
+    }
+}
+
+/// Forgets the recorded key and passphrase salt, once nothing in `root` is
+/// encrypted any more, so that encryption can later start afresh.
+pub fn forget_key(root: &Path) -> Result<()> {
+    for file in [CHECK_FILE, SALT_FILE] {
+        let path = root.join(file);
+        if path.exists() {
+            fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_passphrase() -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("History is encrypted with a passphrase; set {} or run spi in a terminal", PASSPHRASE_ENV));
+    }
+    rpassword::prompt_password("History passphrase: ").context("Failed to read the passphrase")
+}
+
+/// The cipher selected by `encryption` in the `[history]` config, or
+/// `None` if conversations are stored in plain text. `root` is where the
+/// passphrase salt and key check are kept.
+pub fn load_cipher(config: &HistoryConfig, root: &Path) -> Result<Option<Cipher>> {
+    let cipher = match config.encryption.as_str() {
+        "none" => return Ok(None),
+        "keyring" => Cipher::from_keyring()?,
+        "passphrase" => Cipher::from_passphrase(&read_passphrase()?, &load_salt(root)?)?,
+        "key_file" => {
+            let path = config
+                .key_file
+                .as_ref()
+                .ok_or_else(|| anyhow!("encryption = \"key_file\" needs key_file = \"<path>\" under [history]"))?;
+            Cipher::from_key_file(path)?
+        },
+        other => {
+            return Err(anyhow!(
+                "Unknown history encryption '{}'; expected \"none\", \"keyring\", \"passphrase\" or \"key_file\"",
+                other
+            ))
+        },
+    };
+
+    verify_key(&cipher, root)?;
+    Ok(Some(cipher))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_tamper_detection() {
+        let cipher = Cipher::new(&[7; KEY_LENGTH]);
+        let encrypted = cipher.encrypt(b"fn secret() {}");
+
+        assert!(is_encrypted(&encrypted) && !is_encrypted(b"{\"title\": \"x\"}"));
+        assert!(!encrypted.windows(6).any(|window| window == b"secret"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), b"fn secret() {}");
+        assert_ne!(cipher.encrypt(b"fn secret() {}"), encrypted);
+
+        assert!(Cipher::new(&[8; KEY_LENGTH]).decrypt(&encrypted).is_err());
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.decrypt(&tampered).is_err());
+        assert!(cipher.decrypt(&encrypted[..MAGIC.len() + 4]).is_err());
+
+        let first = Cipher::from_passphrase("hunter2", b"0123456789abcdef").unwrap();
+        let second = Cipher::from_passphrase("hunter2", b"0123456789abcdef").unwrap();
+        assert!(second.decrypt(&first.encrypt(b"hi")).is_ok());
+        assert_eq!(decode_hex(&encode_hex(&[0xab; KEY_LENGTH])), Some([0xab; KEY_LENGTH]));
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::clients::openai::message_json;
+use crate::clients::ChatMessage;
+use crate::core::history::{Conversation, Message, Role};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Readable Markdown, for pasting into docs and PR descriptions.
+    Markdown,
+    /// A standalone HTML page.
+    Html,
+    /// One stored message per line, as JSON.
+    Jsonl,
+    /// A single `{"messages": [...]}` line in OpenAI's fine-tuning format.
+    OpenAi,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "openai" => Ok(ExportFormat::OpenAi),
+            _ => Err(anyhow!("Unknown export format '{}'; expected md, html, jsonl or openai", s)),
+        }
+    }
+}
+
+/// Renders the current messages of a conversation; replaced alternatives
+/// are left out.
+pub fn export(conversation: &Conversation, format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(conversation)),
+        ExportFormat::Html => Ok(to_html(conversation)),
+        ExportFormat::Jsonl => to_jsonl(conversation),
+        ExportFormat::OpenAi => to_openai(conversation),
+    }
+}
+
+fn role_label(role: Role) -> &'static str {
+    match role {
+        Role::System => "System",
+        Role::User => "User",
+        Role::Assistant => "Assistant",
+        Role::Tool => "Tool",
+    }
+}
+
+/// Heading for a message, e.g. "Tool (call_1)".
+fn message_heading(message: &Message) -> String {
+    let mut heading = role_label(message.role).to_string();
+    if let Some(call_id) = &message.tool_call_id {
+        heading.push_str(&format!(" ({})", call_id));
+    }
+    if message.interrupted {
+        heading.push_str(" [interrupted]");
+    }
+    heading
+}
+
+/// A code fence longer than any run of backticks in `content`.
+fn fence_for(content: &str) -> String {
+    let longest = content
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn to_markdown(conversation: &Conversation) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# {}\n", conversation.title);
+    let _ = writeln!(out, "_Created {}_\n", conversation.created_at.format("%Y-%m-%d %H:%M UTC"));
+
+    if let Some(system_prompt) = &conversation.system_prompt {
+        let _ = writeln!(out, "**System prompt:** {}\n", system_prompt);
+    }
+
+    for message in &conversation.messages {
+        let _ = writeln!(out, "## {}\n", message_heading(message));
+
+        if message.role == Role::Tool {
+            let fence = fence_for(&message.content);
+            let _ = writeln!(out, "{}\n{}\n{}\n", fence, message.content, fence);
+        } else if !message.content.is_empty() {
+            let _ = writeln!(out, "{}\n", message.content);
+        }
+
+        for call in &message.tool_calls {
+            let _ = writeln!(out, "Tool call `{}` ({}):\n", call.name, call.id);
+            let fence = fence_for(&call.arguments);
+            let _ = writeln!(out, "{}json\n{}\n{}\n", fence, call.arguments, fence);
+        }
+    }
+
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_html(conversation: &Conversation) -> String {
+    let mut out = String::new();
+    let title = escape_html(&conversation.title);
+
+    let _ = writeln!(out, "<!DOCTYPE html>");
+    let _ = writeln!(out, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", title);
+    let _ = writeln!(
+        out,
+        "<style>\n\
+         body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; }}\n\
+         .message {{ border-left: 3px solid #ccc; padding: 0.2em 1em; margin: 1em 0; }}\n\
+         .user {{ border-color: #4a7; }}\n\
+         .assistant {{ border-color: #47a; }}\n\
+         .content {{ white-space: pre-wrap; }}\n\
+         </style>\n</head>\n<body>"
+    );
+    let _ = writeln!(out, "<h1>{}</h1>", title);
+    let _ = writeln!(out, "<p><em>Created {}</em></p>", conversation.created_at.format("%Y-%m-%d %H:%M UTC"));
+
+    if let Some(system_prompt) = &conversation.system_prompt {
+        let _ = writeln!(
+            out,
+            "<div class=\"message system\"><h3>System prompt</h3><div class=\"content\">{}</div></div>",
+            escape_html(system_prompt)
+        );
+    }
+
+    for message in &conversation.messages {
+        let _ = writeln!(out, "<div class=\"message {}\">", message.role);
+        let _ = writeln!(out, "<h3>{}</h3>", escape_html(&message_heading(message)));
+        if !message.content.is_empty() {
+            let _ = writeln!(out, "<div class=\"content\">{}</div>", escape_html(&message.content));
+        }
+        for call in &message.tool_calls {
+            let _ = writeln!(
+                out,
+                "<p>Tool call <code>{}</code> ({}):</p>\n<pre>{}</pre>",
+                escape_html(&call.name),
+                escape_html(&call.id),
+                escape_html(&call.arguments)
+            );
+        }
+        let _ = writeln!(out, "</div>");
+    }
+
+    let _ = writeln!(out, "</body>\n</html>");
+    out
+}
+
+fn to_jsonl(conversation: &Conversation) -> Result<String> {
+    let mut out = String::new();
+
+    for message in &conversation.messages {
+        let message = Message {
+            alternatives: Vec::new(),
+            ..message.clone()
+        };
+        out.push_str(&serde_json::to_string(&message)?);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+fn to_openai(conversation: &Conversation) -> Result<String> {
+    let mut messages: Vec<Value> = Vec::new();
+
+    if let Some(system_prompt) = &conversation.system_prompt {
+        messages.push(message_json(&ChatMessage::new(Role::System, system_prompt)));
+    }
+
+    messages.extend(conversation.messages.iter().map(|msg| {
+        message_json(&ChatMessage {
+            tool_calls: msg.tool_calls.clone(),
+            tool_call_id: msg.tool_call_id.clone(),
+            ..ChatMessage::new(msg.role, &msg.content)
+        })
+    }));
+
+    Ok(format!("{}\n", serde_json::to_string(&json!({ "messages": messages }))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{ChatResponse, ToolCall};
+
+    fn conversation() -> Conversation {
+        let (_, mut conversation) = Conversation::new("Files <2>".to_string());
+        conversation.system_prompt = Some("Be brief.".to_string());
+        conversation.add_user_message("List files".to_string());
+        conversation.add_assistant_reply(&ChatResponse {
+            content: String::new(),
+            model: "m".to_string(),
+            tool_calls: vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "shell".to_string(),
+                arguments: r#"{"cmd":"ls"}"#.to_string(),
+            }],
+            usage: None,
+            raw: Value::Null,
+        });
+        conversation.add_tool_message("call_1".to_string(), "a.txt\n```b```".to_string());
+        conversation
+    }
+
+    #[test]
+    fn test_exports_markdown_and_html() {
+        let markdown = export(&conversation(), ExportFormat::Markdown).unwrap();
+        assert!(markdown.starts_with("# Files <2>\n"));
+        assert!(markdown.contains("**System prompt:** Be brief."));
+        assert!(markdown.contains("## Tool (call_1)\n\n````\na.txt\n```b```\n````"));
+
+        let html = export(&conversation(), ExportFormat::Html).unwrap();
+        assert!(html.contains("<h1>Files &lt;2&gt;</h1>"));
+        assert!(html.contains("<div class=\"message user\">"));
+    }
+
+    #[test]
+    fn test_exports_openai_fine_tuning_shape() {
+        let line = export(&conversation(), ExportFormat::OpenAi).unwrap();
+        assert_eq!(line.lines().count(), 1);
+
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        let messages = parsed["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["name"], "shell");
+        assert_eq!(messages[3]["tool_call_id"], "call_1");
+
+        let jsonl = export(&conversation(), ExportFormat::Jsonl).unwrap();
+        assert_eq!(jsonl.lines().count(), 3);
+        assert!("jsonl".parse::<ExportFormat>().is_ok() && "pdf".parse::<ExportFormat>().is_err());
+    }
+}
@@ -1,27 +1,233 @@
 // Copyright (c) 2025 SharPi Contributors
 // MIT License
 
-use anyhow::{Context, Result};
+use crate::clients::{ChatResponse, ToolCall, Usage};
+use crate::config;
+use crate::core::store::{self, ConversationLock, HistoryStore};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
+use serde_json::Value;
+use std::fmt;
 use std::path::PathBuf;
-use chrono::{DateTime, Utc};
+use std::str::FromStr;
+use chrono::{DateTime, NaiveDate, Utc};
 use uuid::Uuid;
 
+/// Version of the conversation file format written by this build. Files
+/// without a `schema_version` are version 1.
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+/// Title of conversations created implicitly, until one is chosen for them
+/// after the first exchange (see `core::title`).
+pub const DEFAULT_TITLE: &str = "Default Conversation";
+
+/// Shortest ID prefix `History::resolve` accepts, as with git, so that a
+/// short title isn't mistaken for one.
+pub const MIN_PREFIX_LENGTH: usize = 4;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "system" => Ok(Role::System),
+            "user" => Ok(Role::User),
+            "assistant" => Ok(Role::Assistant),
+            "tool" => Ok(Role::Tool),
+            _ => Err(anyhow!("Unknown message role '{}'", s)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
-    pub role: String,  // "user" or "assistant"
+    pub role: Role,
     pub content: String,
     pub timestamp: DateTime<Utc>,
+    /// Set when the reply stream broke off and `content` is incomplete.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    /// Tool calls requested by an assistant message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// For "tool" messages, the ID of the call this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Tokens spent producing an assistant message, if the provider said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Model that produced an assistant message, as reported by the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Earlier versions of this message replaced by an edit or retry, oldest
+    /// first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
+}
+
+/// A replaced version of a message, with the messages that followed it at
+/// the time.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Alternative {
+    pub message: Message,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub following: Vec<Message>,
+    pub replaced_at: DateTime<Utc>,
+}
+
+impl Message {
+    pub fn new(role: Role, content: String) -> Self {
+        Self {
+            role,
+            content,
+            timestamp: Utc::now(),
+            interrupted: false,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            usage: None,
+            model: None,
+            alternatives: Vec::new(),
+        }
+    }
+
+    /// Makes `self` the current version of `old`, keeping `old` and the
+    /// messages that followed it as alternatives.
+    fn replace(mut self, mut old: Message, following: Vec<Message>) -> Self {
+        self.alternatives = std::mem::take(&mut old.alternatives);
+        self.alternatives.push(Alternative {
+            message: old,
+            following,
+            replaced_at: Utc::now(),
+        });
+        self
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Conversation {
+    pub schema_version: u32,
     pub title: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub messages: Vec<Message>,
+    /// Sum of the usage of every request made for the conversation: its
+    /// messages (including replaced ones), summaries and `other_usage`.
+    #[serde(default)]
+    pub usage: Usage,
+    /// Stands in for the oldest messages when sending the conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ConversationSummary>,
+    /// Usage of requests made for the conversation whose result is not a
+    /// message or the current summary, such as titles and earlier summaries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_usage: Vec<UsageEntry>,
+    /// Sent as a system message ahead of every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "ConversationSettings::is_empty")]
+    pub settings: ConversationSettings,
+    /// Where this conversation was forked from, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<ForkOrigin>,
+    /// Labels for organising conversations; see `add_tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Listed ahead of other conversations.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Left out of listings unless asked for.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+    /// The reply being regenerated by a retry, until its replacement arrives.
+    #[serde(skip)]
+    retrying: Option<(Message, Vec<Message>)>,
+}
+
+/// The conversation and message a fork was taken from. The fork starts with
+/// copies of the parent's messages up to and including `message_index`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ForkOrigin {
+    pub conversation_id: String,
+    pub message_index: usize,
+}
+
+/// Per-conversation overrides for the client's request settings.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ConversationSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+impl ConversationSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A model-written summary of the first `covers` messages of a
+/// conversation. The messages themselves are kept; only requests use the
+/// summary in their place.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConversationSummary {
+    pub content: String,
+    pub covers: usize,
+    pub created_at: DateTime<Utc>,
+    /// Tokens spent writing the summary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// Forgets the usage recorded on `messages` and the alternatives kept with
+/// them, for copies that must not be counted twice.
+fn clear_usage(messages: &mut [Message]) {
+    for message in messages {
+        message.usage = None;
+        for alternative in &mut message.alternatives {
+            clear_usage(std::slice::from_mut(&mut alternative.message));
+            clear_usage(&mut alternative.following);
+        }
+    }
+}
+
+/// Tokens spent on one request, with the model that served it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UsageEntry {
+    pub usage: Usage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub timestamp: DateTime<Utc>,
 }
 
 impl Conversation {
@@ -30,66 +236,402 @@ impl Conversation {
         let id = Uuid::new_v4().to_string();
 
         let conversation = Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
             title,
             created_at: now,
             updated_at: now,
             messages: Vec::new(),
+            usage: Usage::default(),
+            summary: None,
+            other_usage: Vec::new(),
+            system_prompt: None,
+            settings: ConversationSettings::default(),
+            forked_from: None,
+            tags: Vec::new(),
+            pinned: false,
+            archived: false,
+            retrying: None,
         };
 
         (id, conversation)
     }
 
-    pub fn add_user_message(&mut self, content: String) {
-        self.messages.push(Message {
-            role: "user".to_string(),
-            content,
-            timestamp: Utc::now(),
-        });
+    fn push(&mut self, message: Message) {
+        self.messages.push(message);
         self.updated_at = Utc::now();
     }
 
+    pub fn add_user_message(&mut self, content: String) {
+        self.push(Message::new(Role::User, content));
+    }
+
     pub fn add_assistant_message(&mut self, content: String) {
-        self.messages.push(Message {
-            role: "assistant".to_string(),
-            content,
-            timestamp: Utc::now(),
+        self.push(Message::new(Role::Assistant, content));
+    }
+
+    pub fn add_interrupted_assistant_message(&mut self, content: String) {
+        self.push_reply(Message {
+            interrupted: true,
+            ..Message::new(Role::Assistant, content)
+        });
+    }
+
+    /// Adds a model reply with any tool calls it made, counting its usage
+    /// towards the conversation total.
+    pub fn add_assistant_reply(&mut self, response: &ChatResponse) {
+        if let Some(usage) = response.usage {
+            self.usage += usage;
+        }
+        self.push_reply(Message {
+            tool_calls: response.tool_calls.clone(),
+            usage: response.usage,
+            model: Some(response.model.clone()),
+            ..Message::new(Role::Assistant, response.content.clone())
         });
+    }
+
+    /// Pushes a reply, keeping the one it replaces if this is a retry.
+    fn push_reply(&mut self, message: Message) {
+        match self.retrying.take() {
+            Some((old, following)) => self.push(message.replace(old, following)),
+            None => self.push(message),
+        }
+    }
+
+    /// Removes the reply to the last user message (the assistant message and
+    /// any tool calls and results after it) so it can be generated again.
+    /// The next reply added keeps the removed one as an alternative; until
+    /// then `cancel_retry` puts it back.
+    ///
+    /// Returns false, changing nothing, if the last user message has no
+    /// reply yet.
+    pub fn start_retry(&mut self) -> Result<bool> {
+        let last_user = self
+            .messages
+            .iter()
+            .rposition(|message| message.role == Role::User)
+            .ok_or_else(|| anyhow!("There is no user message to reply to"))?;
+
+        if last_user + 1 == self.messages.len() {
+            return Ok(false);
+        }
+
+        let mut removed = self.messages.split_off(last_user + 1);
+        let old = removed.remove(0);
+        self.retrying = Some((old, removed));
         self.updated_at = Utc::now();
+        Ok(true)
+    }
+
+    /// Restores the reply removed by `start_retry` if no new one was added.
+    pub fn cancel_retry(&mut self) {
+        if let Some((old, following)) = self.retrying.take() {
+            self.messages.push(old);
+            self.messages.extend(following);
+        }
+    }
+
+    /// Replaces the content of message `index` and removes the messages
+    /// after it. The old version and the removed messages are kept as an
+    /// alternative on the edited message.
+    pub fn edit_message(&mut self, index: usize, content: String) -> Result<()> {
+        if index >= self.messages.len() {
+            return Err(anyhow!(
+                "Message index {} is out of range; the conversation has {} messages",
+                index,
+                self.messages.len()
+            ));
+        }
+
+        let following = self.messages.split_off(index + 1);
+        let old = self.messages.pop().expect("index is in range");
+        let edited = Message {
+            tool_call_id: old.tool_call_id.clone(),
+            ..Message::new(old.role, content)
+        };
+        self.push(edited.replace(old, following));
+
+        if self.summary.as_ref().is_some_and(|summary| summary.covers > index) {
+            self.summary = None;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a tag unless the conversation already has it (in any case).
+    /// Returns false if it did, or if the tag is blank.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let tag = tag.trim();
+        if tag.is_empty() || self.has_tag(tag) {
+            return false;
+        }
+        self.tags.push(tag.to_string());
+        true
+    }
+
+    /// Returns false if the conversation did not have the tag.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let before = self.tags.len();
+        self.tags.retain(|existing| !existing.eq_ignore_ascii_case(tag.trim()));
+        self.tags.len() != before
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|existing| existing.eq_ignore_ascii_case(tag.trim()))
+    }
+
+    /// Counts the usage of a request made for the conversation whose result
+    /// isn't stored as a message, such as writing its title.
+    pub fn record_usage(&mut self, entry: UsageEntry) {
+        self.usage += entry.usage;
+        self.other_usage.push(entry);
+    }
+
+    pub fn add_tool_message(&mut self, tool_call_id: String, content: String) {
+        self.push(Message {
+            tool_call_id: Some(tool_call_id),
+            ..Message::new(Role::Tool, content)
+        });
+    }
+
+    /// Every message, including replaced versions kept as alternatives and
+    /// the messages that followed them.
+    pub fn all_messages(&self) -> Vec<&Message> {
+        fn collect<'a>(messages: &'a [Message], out: &mut Vec<&'a Message>) {
+            for message in messages {
+                out.push(message);
+                for alternative in &message.alternatives {
+                    collect(std::slice::from_ref(&alternative.message), out);
+                    collect(&alternative.following, out);
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        collect(&self.messages, &mut out);
+        out
+    }
+
+    /// Tool calls from the last assistant message that have no result yet.
+    pub fn pending_tool_calls(&self) -> Vec<&ToolCall> {
+        let Some(index) = self.messages.iter().rposition(|msg| msg.role == Role::Assistant) else {
+            return Vec::new();
+        };
+
+        let answered: Vec<&str> = self.messages[index + 1..]
+            .iter()
+            .filter_map(|msg| msg.tool_call_id.as_deref())
+            .collect();
+
+        self.messages[index]
+            .tool_calls
+            .iter()
+            .filter(|call| !answered.contains(&call.id.as_str()))
+            .collect()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// Conversations and which one is active, kept in a `HistoryStore`.
 pub struct History {
     pub active_conversation_id: Option<String>,
+    store: Box<dyn HistoryStore>,
 }
 
 impl History {
+    /// Opens the history kept in `store`.
+    pub fn open(store: Box<dyn HistoryStore>) -> Result<Self> {
+        Ok(Self {
+            active_conversation_id: store.load_active_id()?,
+            store,
+        })
+    }
+
+    pub fn store(&self) -> &dyn HistoryStore {
+        self.store.as_ref()
+    }
+
     pub fn create_conversation(&mut self, title: String) -> Result<(String, Conversation)> {
-        let (id, conversation) = Conversation::new(title);
+        self.create_conversation_with_settings(title, None, ConversationSettings::default())
+    }
 
-        save_conversation(&id, &conversation)?;
+    /// Creates a conversation with its own system prompt and request
+    /// settings, e.g. a "reviewer" persona, and makes it active.
+    pub fn create_conversation_with_settings(
+        &mut self,
+        title: String,
+        system_prompt: Option<String>,
+        settings: ConversationSettings,
+    ) -> Result<(String, Conversation)> {
+        let (id, mut conversation) = Conversation::new(title);
+        conversation.system_prompt = system_prompt;
+        conversation.settings = settings;
+
+        self.store.save_conversation(&id, &conversation)?;
         self.active_conversation_id = Some(id.clone());
-        save_active_conversation_id(&self.active_conversation_id)?;
+        self.save()?;
 
-        let loaded_conversation = load_conversation(&id)?;
+        let loaded_conversation = self.store.load_conversation(&id)?;
         Ok((id, loaded_conversation))
     }
 
+    /// Creates a new conversation holding the messages of `id` up to and
+    /// including `message_index` (all of them if `None`), with the same
+    /// system prompt and settings, and makes it active. The original is left
+    /// untouched.
+    ///
+    /// The copied messages and summary were paid for by the original, so
+    /// their usage is cleared; the fork's usage starts at zero, and usage
+    /// reports count only what is spent in it after forking.
+    pub fn fork_conversation(&mut self, id: &str, message_index: Option<usize>) -> Result<(String, Conversation)> {
+        let parent = self.get_conversation(id)?;
+
+        if parent.messages.is_empty() {
+            return Err(anyhow!("Conversation {} has no messages to fork from", id));
+        }
+        let message_index = message_index.unwrap_or(parent.messages.len() - 1);
+        if message_index >= parent.messages.len() {
+            return Err(anyhow!(
+                "Message index {} is out of range; conversation {} has {} messages",
+                message_index,
+                id,
+                parent.messages.len()
+            ));
+        }
+
+        let title = if parent.title.ends_with(" (fork)") {
+            parent.title.clone()
+        } else {
+            format!("{} (fork)", parent.title)
+        };
+        let (fork_id, mut fork) = self.create_conversation(title)?;
+
+        fork.messages = parent.messages[..=message_index].to_vec();
+        clear_usage(&mut fork.messages);
+        fork.summary = parent.summary.filter(|summary| summary.covers <= fork.messages.len());
+        if let Some(summary) = &mut fork.summary {
+            summary.usage = None;
+        }
+        fork.system_prompt = parent.system_prompt;
+        fork.settings = parent.settings;
+        fork.forked_from = Some(ForkOrigin {
+            conversation_id: id.to_string(),
+            message_index,
+        });
+
+        self.save_conversation(&fork_id, &fork)?;
+        Ok((fork_id, fork))
+    }
+
+    /// The chain of conversations `id` was forked from, nearest first. Stops
+    /// at the first ancestor that no longer exists.
+    pub fn lineage(&self, id: &str) -> Result<Vec<(String, Conversation)>> {
+        let mut ancestors: Vec<(String, Conversation)> = Vec::new();
+        let mut origin = self.get_conversation(id)?.forked_from;
+
+        while let Some(ForkOrigin { conversation_id, .. }) = origin {
+            // Guard against hand-edited files that form a cycle.
+            if conversation_id == id || ancestors.iter().any(|(ancestor, _)| *ancestor == conversation_id) {
+                break;
+            }
+            let Ok(parent) = self.get_conversation(&conversation_id) else {
+                break;
+            };
+            origin = parent.forked_from.clone();
+            ancestors.push((conversation_id, parent));
+        }
+
+        Ok(ancestors)
+    }
+
     pub fn get_conversation(&self, id: &str) -> Result<Conversation> {
-        load_conversation(id)
+        self.store.load_conversation(id)
+    }
+
+    /// Finds the ID of the conversation a user means by `reference`: a full
+    /// ID, an ID prefix of at least `MIN_PREFIX_LENGTH` characters, `@last`
+    /// or `@prev` (the most and second most recently updated conversation
+    /// that isn't archived), or an exact title. Titles are matched ignoring
+    /// case only if none matches exactly. Fails, listing the candidates, if
+    /// more than one conversation matches.
+    pub fn resolve(&self, reference: &str) -> Result<String> {
+        let reference = reference.trim();
+        if reference.is_empty() {
+            return Err(anyhow!("No conversation given"));
+        }
+        if store::is_valid_id(reference) && self.store.conversation_exists(reference)? {
+            return Ok(reference.to_string());
+        }
+
+        let conversations = self.list_conversations(&ListOptions {
+            include_archived: true,
+            ..ListOptions::default()
+        })?;
+
+        let recent = match reference {
+            "@last" => Some(0),
+            "@prev" => Some(1),
+            _ => None,
+        };
+        if let Some(position) = recent {
+            let mut unarchived: Vec<_> = conversations.iter().filter(|(_, metadata)| !metadata.archived).collect();
+            // Pinned conversations come first in listings, but not here.
+            unarchived.sort_by_key(|(_, metadata)| std::cmp::Reverse(metadata.updated_at));
+            return unarchived
+                .get(position)
+                .map(|(id, _)| id.clone())
+                .ok_or_else(|| anyhow!("There is no {} conversation", reference));
+        }
+
+        let prefix = reference.to_lowercase();
+        let mut candidates: Vec<&(String, ConversationMetadata)> = conversations
+            .iter()
+            .filter(|(id, metadata)| {
+                (prefix.len() >= MIN_PREFIX_LENGTH && id.starts_with(&prefix)) || metadata.title == reference
+            })
+            .collect();
+        if candidates.is_empty() {
+            candidates = conversations
+                .iter()
+                .filter(|(_, metadata)| metadata.title.to_lowercase() == prefix)
+                .collect();
+        }
+
+        match candidates.as_slice() {
+            [] => Err(anyhow!("No conversation matches '{}'", reference)),
+            [(id, _)] => Ok(id.clone()),
+            _ => {
+                let listing: Vec<String> = candidates
+                    .iter()
+                    .map(|(id, metadata)| {
+                        format!("  {} - {} (updated {})", id, metadata.title, metadata.updated_at.format("%Y-%m-%d %H:%M"))
+                    })
+                    .collect();
+                Err(anyhow!("'{}' is ambiguous; it matches:\n{}", reference, listing.join("\n")))
+            },
+        }
+    }
+
+    pub fn save_conversation(&self, id: &str, conversation: &Conversation) -> Result<()> {
+        self.store.save_conversation(id, conversation)
+    }
+
+    /// Locks a conversation against changes by other processes until the
+    /// returned lock is dropped. Load the conversation after taking it.
+    pub fn lock_conversation(&self, id: &str) -> Result<ConversationLock> {
+        self.store.lock_conversation(id)
     }
 
     pub fn get_active_conversation(&self) -> Result<Option<Conversation>> {
         match &self.active_conversation_id {
-            Some(id) => Ok(Some(load_conversation(id)?)),
+            Some(id) => Ok(Some(self.store.load_conversation(id)?)),
             None => Ok(None),
         }
     }
 
     pub fn set_active_conversation(&mut self, id: String) -> Result<bool> {
-        if conversation_exists(&id)? {
+        if self.store.conversation_exists(&id)? {
             self.active_conversation_id = Some(id);
-            save_active_conversation_id(&self.active_conversation_id)?;
+            self.save()?;
             Ok(true)
         } else {
             Ok(false)
@@ -100,167 +642,473 @@ impl History {
         match self.get_active_conversation()? {
             Some(conversation) => Ok((self.active_conversation_id.clone().unwrap(), conversation)),
             None => {
-                let (id, conversation) = self.create_conversation("Default Conversation".to_string())?;
+                let (id, conversation) = self.create_conversation(DEFAULT_TITLE.to_string())?;
                 Ok((id, conversation))
             }
         }
     }
 
-    pub fn list_conversations(&self) -> Result<HashMap<String, ConversationMetadata>> {
-        let conversations_dir = get_conversations_dir()?;
-        let mut conversations = HashMap::new();
-
-        if !conversations_dir.exists() {
-            return Ok(conversations);
-        }
+    /// Lists conversations in the order and range given by `options`.
+    pub fn list_conversations(&self, options: &ListOptions) -> Result<Vec<(String, ConversationMetadata)>> {
+        let mut conversations = self.store.list_metadata()?;
 
-        for entry in fs::read_dir(conversations_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        conversations.retain(|(_, metadata)| {
+            let day = options.sort.date_of(metadata).date_naive();
+            (options.include_archived || !metadata.archived)
+                && options.tag.as_ref().map_or(true, |tag| metadata.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+                && !options.since.is_some_and(|since| day < since)
+                && !options.until.is_some_and(|until| day > until)
+        });
 
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
-                if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
-                    match load_conversation(id) {
-                        Ok(conversation) => {
-                            let metadata = ConversationMetadata {
-                                title: conversation.title.clone(),
-                                message_count: conversation.messages.len(),
-                                created_at: conversation.created_at,
-                                updated_at: conversation.updated_at,
-                            };
-                            conversations.insert(id.to_string(), metadata);
-                        },
-                        Err(_) => continue,
-                    }
-                }
-            }
+        match options.sort {
+            SortOrder::Updated => conversations.sort_by_key(|(_, metadata)| std::cmp::Reverse(metadata.updated_at)),
+            SortOrder::Created => conversations.sort_by_key(|(_, metadata)| std::cmp::Reverse(metadata.created_at)),
+            SortOrder::Title => conversations.sort_by_cached_key(|(_, metadata)| metadata.title.to_lowercase()),
         }
+        // Stable, so pinned conversations keep the chosen order among themselves.
+        conversations.sort_by_key(|(_, metadata)| !metadata.pinned);
 
+        if let Some(limit) = options.limit {
+            conversations.truncate(limit);
+        }
         Ok(conversations)
     }
 
+    /// Loads every stored conversation, skipping (with a warning) ones that
+    /// fail to load.
+    pub fn load_all_conversations(&self) -> Result<Vec<(String, Conversation)>> {
+        Ok(store::load_all(self.store.as_ref()))
+    }
+
+    /// Finds conversation titles and messages matching `query`.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        self.store.search(query, limit)
+    }
+
     pub fn remove_conversation(&mut self, id: &str) -> Result<()> {
-        if !conversation_exists(id)? {
+        if !self.store.conversation_exists(id)? {
             return Err(anyhow::anyhow!("Conversation with ID {} does not exist", id));
         }
 
-        let path = get_conversation_path(id)?;
-        fs::remove_file(&path)
-            .context(format!("Failed to delete conversation file: {}", path.display()))?;
+        self.store.delete_conversation(id)?;
 
         if self.active_conversation_id.as_deref() == Some(id) {
             self.active_conversation_id = None;
-            save_active_conversation_id(&self.active_conversation_id)?;
+            self.save()?;
         }
 
         Ok(())
     }
+
+    /// Persists which conversation is active.
+    pub fn save(&self) -> Result<()> {
+        self.store.save_active_id(self.active_conversation_id.as_deref())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConversationMetadata {
     pub title: String,
     pub message_count: usize,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub usage: Usage,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub archived: bool,
 }
 
-fn get_sharpi_dir() -> Result<PathBuf> {
-    let home = dirs::home_dir().context("Could not find home directory")?;
-    let sharpi_dir = home.join(".sharpi");
-
-    if !sharpi_dir.exists() {
-        fs::create_dir_all(&sharpi_dir)
-            .context(format!("Failed to create directory: {}", sharpi_dir.display()))?;
+impl ConversationMetadata {
+    pub fn from_conversation(conversation: &Conversation) -> Self {
+        Self {
+            title: conversation.title.clone(),
+            message_count: conversation.messages.len(),
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+            usage: conversation.usage,
+            tags: conversation.tags.clone(),
+            pinned: conversation.pinned,
+            archived: conversation.archived,
+        }
     }
-
-    Ok(sharpi_dir)
 }
 
-fn get_conversations_dir() -> Result<PathBuf> {
-    let conversations_dir = get_sharpi_dir()?.join("conversations");
+/// Order of `History::list_conversations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Most recently updated first.
+    #[default]
+    Updated,
+    /// Most recently created first.
+    Created,
+    /// Alphabetically, ignoring case.
+    Title,
+}
 
-    if !conversations_dir.exists() {
-        fs::create_dir_all(&conversations_dir)
-            .context(format!("Failed to create directory: {}", conversations_dir.display()))?;
+impl SortOrder {
+    /// The date that `--since` and `--until` apply to: when the
+    /// conversation was created if sorting by that, otherwise when it was
+    /// last updated.
+    fn date_of(self, metadata: &ConversationMetadata) -> DateTime<Utc> {
+        match self {
+            SortOrder::Created => metadata.created_at,
+            SortOrder::Updated | SortOrder::Title => metadata.updated_at,
+        }
     }
-
-    Ok(conversations_dir)
 }
 
-fn get_conversation_path(id: &str) -> Result<PathBuf> {
-    let path = get_conversations_dir()?.join(format!("{}.json", id));
-    Ok(path)
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "updated" => Ok(SortOrder::Updated),
+            "created" => Ok(SortOrder::Created),
+            "title" => Ok(SortOrder::Title),
+            _ => Err(anyhow!("Unknown sort order '{}'; expected updated, created or title", s)),
+        }
+    }
 }
 
-fn get_active_conversation_path() -> Result<PathBuf> {
-    let path = get_sharpi_dir()?.join("active_conversation.json");
-    Ok(path)
+/// Which conversations `History::list_conversations` returns, and in what
+/// order. Pinned conversations always come first.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub sort: SortOrder,
+    /// Only conversations with this tag (ignoring case).
+    pub tag: Option<String>,
+    pub include_archived: bool,
+    pub limit: Option<usize>,
+    /// First day (UTC) to include.
+    pub since: Option<NaiveDate>,
+    /// Last day (UTC) to include.
+    pub until: Option<NaiveDate>,
 }
 
-fn conversation_exists(id: &str) -> Result<bool> {
-    let path = get_conversation_path(id)?;
-    Ok(path.exists())
+/// A conversation title or message matching a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub conversation_id: String,
+    pub title: String,
+    /// The matching message, or `None` if the title matched.
+    pub message_index: Option<usize>,
+    /// Excerpt of the match, with the matched words in brackets.
+    pub snippet: String,
 }
 
-pub fn save_conversation(id: &str, conversation: &Conversation) -> Result<()> {
-    let path = get_conversation_path(id)?;
+/// Parses a stored conversation, upgrading it from an older schema version
+/// if needed. Also returns the version it was upgraded from, if any, so the
+/// store can write the new form back.
+pub(crate) fn parse_conversation(content: &str) -> Result<(Conversation, Option<u32>)> {
+    let value: Value = serde_json::from_str(content).context("Conversation is not valid JSON")?;
 
-    let json = serde_json::to_string_pretty(conversation)
-        .context("Failed to serialize conversation to JSON")?;
+    let (value, migrated_from) = migrate_conversation(value).context("Failed to upgrade conversation")?;
 
-    fs::write(&path, json)
-        .context(format!("Failed to write conversation file: {}", path.display()))?;
+    let conversation = serde_json::from_value(value).context("Failed to parse conversation")?;
 
-    Ok(())
+    Ok((conversation, migrated_from))
 }
 
-fn load_conversation(id: &str) -> Result<Conversation> {
-    let path = get_conversation_path(id)?;
+/// Brings a conversation file's JSON up to `CURRENT_SCHEMA_VERSION`, one
+/// version at a time. Returns the version it started from if anything
+/// changed, so the caller can write the upgraded file back.
+fn migrate_conversation(mut value: Value) -> Result<(Value, Option<u32>)> {
+    let version = match value.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| anyhow!("Invalid schema_version: {}", version))?,
+    };
 
-    if !path.exists() {
-        return Err(anyhow::anyhow!("Conversation with ID {} does not exist", id));
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Conversation uses schema version {}, but this version of SharPi only understands up to {}; please upgrade",
+            version,
+            CURRENT_SCHEMA_VERSION
+        ));
+    }
+    if version == CURRENT_SCHEMA_VERSION {
+        return Ok((value, None));
     }
 
-    let content = fs::read_to_string(&path)
-        .context(format!("Failed to read conversation file: {}", path.display()))?;
-
-    let conversation: Conversation = serde_json::from_str(&content)
-        .context("Failed to parse conversation file")?;
+    if version < 2 {
+        migrate_v1_to_v2(&mut value)?;
+    }
 
-    Ok(conversation)
+    value["schema_version"] = Value::from(CURRENT_SCHEMA_VERSION);
+    Ok((value, Some(version)))
 }
 
-fn save_active_conversation_id(id: &Option<String>) -> Result<()> {
-    let path = get_active_conversation_path()?;
-
-    let json = serde_json::to_string_pretty(id)
-        .context("Failed to serialize active conversation ID to JSON")?;
+/// Version 1 stored roles as free-form strings. Normalise their case and
+/// reject anything that is not a known role, naming the offending message.
+fn migrate_v1_to_v2(value: &mut Value) -> Result<()> {
+    let Some(messages) = value.get_mut("messages").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
 
-    fs::write(&path, json)
-        .context(format!("Failed to write active conversation ID file: {}", path.display()))?;
+    for (index, message) in messages.iter_mut().enumerate() {
+        let role = message["role"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Message {} has no role", index))?;
+        let role: Role = role
+            .trim()
+            .to_lowercase()
+            .parse()
+            .context(format!("Message {} has an invalid role", index))?;
+        message["role"] = Value::from(role.as_str());
+    }
 
     Ok(())
 }
 
+/// Opens the history in `~/.sharpi`, using the backend chosen under
+/// `[history]` in the config.
 pub fn load_history() -> Result<History> {
-    let path = get_active_conversation_path()?;
+    History::open(store::open_store(&config::load_history_config()?, &sharpi_dir()?)?)
+}
+
+pub(crate) fn sharpi_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Could not find home directory")?;
+    Ok(home.join(".sharpi"))
+}
+
+pub fn save_history(history: &History) -> Result<()> {
+    history.save()
+}
 
-    if !path.exists() {
-        return Ok(History::default());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::store::MemoryStore;
+    use serde_json::json;
+
+    #[test]
+    fn test_history_in_memory_store() {
+        let store = MemoryStore::new();
+        let mut history = History::open(Box::new(store.clone())).unwrap();
+
+        let (id, mut conversation) = history.create_conversation("First".to_string()).unwrap();
+        conversation.add_user_message("hello".to_string());
+        history.save_conversation(&id, &conversation).unwrap();
+
+        let reopened = History::open(Box::new(store.clone())).unwrap();
+        assert_eq!(reopened.active_conversation_id.as_deref(), Some(id.as_str()));
+        assert_eq!(reopened.list_conversations(&ListOptions::default()).unwrap()[0].1.message_count, 1);
+
+        history.remove_conversation(&id).unwrap();
+        assert!(history.active_conversation_id.is_none());
+        assert!(store.conversation_ids().unwrap().is_empty());
+        assert!(history.get_conversation(&id).is_err());
     }
 
-    let content = fs::read_to_string(&path)
-        .context(format!("Failed to read active conversation ID file: {}", path.display()))?;
+    fn reply(content: &str) -> ChatResponse {
+        ChatResponse {
+            content: content.to_string(),
+            model: "m".to_string(),
+            tool_calls: Vec::new(),
+            usage: Some(Usage::new(10, 5)),
+            raw: Value::Null,
+        }
+    }
 
-    let active_conversation_id: Option<String> = serde_json::from_str(&content)
-        .context("Failed to parse active conversation ID file")?;
+    #[test]
+    fn test_fork_conversation() {
+        let mut history = History::open(Box::new(MemoryStore::new())).unwrap();
 
-    Ok(History {
-        active_conversation_id,
-    })
-}
+        let (id, mut conversation) = history.create_conversation("First".to_string()).unwrap();
+        conversation.add_user_message("hello".to_string());
+        conversation.add_assistant_reply(&reply("one"));
+        assert!(conversation.start_retry().unwrap());
+        conversation.add_assistant_reply(&reply("two"));
+        conversation.add_user_message("more".to_string());
+        conversation.add_assistant_reply(&reply("three"));
+        history.save_conversation(&id, &conversation).unwrap();
 
-pub fn save_history(history: &History) -> Result<()> {
-    save_active_conversation_id(&history.active_conversation_id)
+        let (fork_id, fork) = history.fork_conversation(&id, Some(1)).unwrap();
+        assert_eq!(fork.title, "First (fork)");
+        assert_eq!(fork.messages.len(), 2);
+        assert_eq!(fork.messages[1].content, "two");
+        assert_eq!(fork.forked_from, Some(ForkOrigin { conversation_id: id.clone(), message_index: 1 }));
+        assert_eq!(history.active_conversation_id.as_deref(), Some(fork_id.as_str()));
+        assert_eq!(history.get_conversation(&id).unwrap().messages.len(), 4);
+
+        // The copied replies, and the one replaced by a retry, were paid for
+        // by the original; forking adds nothing to the usage report.
+        assert_eq!(fork.usage, Usage::default());
+        let parent = history.get_conversation(&id).unwrap();
+        let report = crate::core::usage::build_report(&[(id.clone(), parent.clone())], |_| None, None, None);
+        let with_fork = crate::core::usage::build_report(&[(id.clone(), parent), (fork_id.clone(), fork)], |_| None, None, None);
+        assert_eq!(report.total.usage.total_tokens, 45);
+        assert_eq!(with_fork.total.usage.total_tokens, 45);
+
+        let (grandchild_id, grandchild) = history.fork_conversation(&fork_id, None).unwrap();
+        assert_eq!(grandchild.title, "First (fork)");
+        let lineage: Vec<String> = history.lineage(&grandchild_id).unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(lineage, [fork_id, id.clone()]);
+
+        assert!(history.fork_conversation(&id, Some(4)).is_err());
+        assert!(history.fork_conversation(&id, Some(usize::MAX)).is_err());
+        let (empty, _) = history.create_conversation("Empty".to_string()).unwrap();
+        assert!(history.fork_conversation(&empty, None).is_err());
+    }
+
+    #[test]
+    fn test_list_sorts_limits_and_filters() {
+        let store = MemoryStore::new();
+        let mut history = History::open(Box::new(store.clone())).unwrap();
+
+        for (title, created, updated) in [("beta", 1, 5), ("Alpha", 3, 3), ("gamma", 2, 9)] {
+            let (id, mut conversation) = history.create_conversation(title.to_string()).unwrap();
+            conversation.created_at = format!("2025-01-0{}T12:00:00Z", created).parse().unwrap();
+            conversation.updated_at = format!("2025-01-0{}T12:00:00Z", updated).parse().unwrap();
+            history.save_conversation(&id, &conversation).unwrap();
+        }
+
+        let titles = |options: ListOptions| -> Vec<String> {
+            history.list_conversations(&options).unwrap().into_iter().map(|(_, metadata)| metadata.title).collect()
+        };
+
+        assert_eq!(titles(ListOptions::default()), ["gamma", "beta", "Alpha"]);
+        assert_eq!(titles(ListOptions { sort: SortOrder::Created, ..Default::default() }), ["Alpha", "gamma", "beta"]);
+        assert_eq!(titles(ListOptions { sort: "title".parse().unwrap(), limit: Some(2), ..Default::default() }), ["Alpha", "beta"]);
+
+        let since = NaiveDate::from_ymd_opt(2025, 1, 4);
+        let until = NaiveDate::from_ymd_opt(2025, 1, 5);
+        assert_eq!(titles(ListOptions { since, ..Default::default() }), ["gamma", "beta"]);
+        assert_eq!(titles(ListOptions { since, until, ..Default::default() }), ["beta"]);
+        assert_eq!(titles(ListOptions { sort: SortOrder::Created, since, ..Default::default() }), Vec::<String>::new());
+        assert!("size".parse::<SortOrder>().is_err());
+    }
+
+    #[test]
+    fn test_tags_pins_and_archive_in_listings() {
+        let store = MemoryStore::new();
+        let mut history = History::open(Box::new(store)).unwrap();
+
+        let mut ids = Vec::new();
+        for title in ["a", "b", "c"] {
+            let (id, conversation) = history.create_conversation(title.to_string()).unwrap();
+            ids.push((id, conversation));
+        }
+        let [(a, mut first), (b, mut second), (c, mut third)] = <[_; 3]>::try_from(ids).unwrap();
+
+        assert!(first.add_tag("Work") && !first.add_tag("work ") && !first.add_tag(" "));
+        first.add_tag("rust");
+        assert!(first.remove_tag("RUST") && !first.remove_tag("rust"));
+        second.add_tag("work");
+        second.pinned = true;
+        third.archived = true;
+        history.save_conversation(&a, &first).unwrap();
+        history.save_conversation(&b, &second).unwrap();
+        history.save_conversation(&c, &third).unwrap();
+
+        let ids = |options: ListOptions| -> Vec<String> {
+            history.list_conversations(&options).unwrap().into_iter().map(|(id, _)| id).collect()
+        };
+
+        assert_eq!(ids(ListOptions { sort: SortOrder::Title, ..Default::default() }), [b.clone(), a.clone()]);
+        assert_eq!(ids(ListOptions { sort: SortOrder::Title, include_archived: true, ..Default::default() }), [b.clone(), a.clone(), c]);
+        assert_eq!(ids(ListOptions { tag: Some("WORK".to_string()), limit: Some(1), ..Default::default() }), [b]);
+
+        let metadata = &history.list_conversations(&ListOptions::default()).unwrap()[1].1;
+        assert_eq!(metadata.tags, ["Work"]);
+    }
+
+    #[test]
+    fn test_resolves_prefixes_recent_and_titles() {
+        let store = MemoryStore::new();
+        let history = History::open(Box::new(store.clone())).unwrap();
+
+        for (id, title, day, pinned) in [
+            ("abcd1111-0000", "Deploy", 1, true),
+            ("abcd2222-0000", "Lifetimes", 2, false),
+            ("ef001111-0000", "deploy", 3, false),
+        ] {
+            let (_, mut conversation) = Conversation::new(title.to_string());
+            conversation.updated_at = format!("2025-01-0{}T12:00:00Z", day).parse().unwrap();
+            conversation.pinned = pinned;
+            store.save_conversation(id, &conversation).unwrap();
+        }
+
+        assert_eq!(history.resolve("abcd2222-0000").unwrap(), "abcd2222-0000");
+        assert_eq!(history.resolve("abcd2").unwrap(), "abcd2222-0000");
+        assert_eq!(history.resolve("EF00").unwrap(), "ef001111-0000");
+        assert_eq!(history.resolve("@last").unwrap(), "ef001111-0000");
+        assert_eq!(history.resolve("@prev").unwrap(), "abcd2222-0000");
+        assert_eq!(history.resolve("Deploy").unwrap(), "abcd1111-0000");
+        assert_eq!(history.resolve("LIFETIMES").unwrap(), "abcd2222-0000");
+
+        let ambiguous = history.resolve("abcd").unwrap_err().to_string();
+        assert!(ambiguous.contains("abcd1111-0000 - Deploy") && ambiguous.contains("abcd2222-0000 - Lifetimes"));
+        assert!(history.resolve("DEPLOY").is_err());
+        assert!(history.resolve("ab").is_err());
+        assert!(history.resolve("../index").is_err());
+        assert!(history.resolve("../abcd2222-0000").is_err());
+        assert!(history.resolve("nothing").is_err());
+    }
+
+    #[test]
+    fn test_retry_and_edit_keep_alternatives() {
+        let (_, mut conversation) = Conversation::new("Edits".to_string());
+        conversation.add_user_message("Write a haiku".to_string());
+        conversation.add_assistant_reply(&reply("first"));
+
+        assert!(conversation.start_retry().unwrap());
+        conversation.cancel_retry();
+        assert_eq!(conversation.messages[1].content, "first");
+
+        assert!(conversation.start_retry().unwrap());
+        conversation.add_assistant_reply(&reply("second"));
+        assert_eq!(conversation.messages.len(), 2);
+        assert_eq!(conversation.messages[1].content, "second");
+        assert_eq!(conversation.messages[1].alternatives[0].message.content, "first");
+        assert_eq!(conversation.usage.total_tokens, 30);
+
+        conversation.edit_message(0, "Write a limerick".to_string()).unwrap();
+        assert_eq!(conversation.messages.len(), 1);
+        assert!(!conversation.start_retry().unwrap());
+        let alternative = &conversation.messages[0].alternatives[0];
+        assert_eq!(alternative.message.content, "Write a haiku");
+        assert_eq!(alternative.following[0].content, "second");
+        assert_eq!(conversation.all_messages().len(), 4);
+
+        assert!(conversation.edit_message(5, "x".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_migrates_unversioned_conversation() {
+        let v1 = json!({
+            "title": "Old",
+            "created_at": "2025-01-01T00:00:00Z",
+            "updated_at": "2025-01-01T00:00:00Z",
+            "messages": [
+                {"role": "user", "content": "hi", "timestamp": "2025-01-01T00:00:00Z"},
+                {"role": "Assistant ", "content": "hello", "timestamp": "2025-01-01T00:00:01Z"}
+            ]
+        });
+
+        let (value, migrated_from) = migrate_conversation(v1).unwrap();
+        assert_eq!(migrated_from, Some(1));
+
+        let conversation: Conversation = serde_json::from_value(value).unwrap();
+        assert_eq!(conversation.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(conversation.messages[1].role, Role::Assistant);
+
+        let current = serde_json::to_value(&conversation).unwrap();
+        assert_eq!(migrate_conversation(current).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_rejects_unknown_roles_and_newer_schemas() {
+        let bad_role = json!({"messages": [{"role": "narrator", "content": ""}]});
+        let err = migrate_conversation(bad_role).unwrap_err();
+        assert!(format!("{:#}", err).contains("Message 0 has an invalid role"));
+
+        let newer = json!({"schema_version": CURRENT_SCHEMA_VERSION + 1});
+        assert!(migrate_conversation(newer).unwrap_err().to_string().contains("please upgrade"));
+    }
 }
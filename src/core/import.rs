@@ -0,0 +1,340 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::clients::ToolCall;
+use crate::core::history::{Conversation, Message, Role};
+use crate::core::title;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Converts an export from another tool into conversations, paired with the
+/// IDs to store them under.
+///
+/// Understands ChatGPT's `conversations.json` (whose conversation IDs are
+/// kept if they are UUIDs, so that importing the same export twice can
+/// skip what was imported before), OpenAI-format JSONL with one
+/// `{"messages": [...]}` object per line, and the `jsonl` format written by
+/// `spi chat export`.
+pub fn import(content: &str) -> Result<Vec<(String, Conversation)>> {
+    let trimmed = content.trim_start();
+
+    if trimmed.starts_with('[') {
+        let value: Value = serde_json::from_str(trimmed).context("Invalid JSON")?;
+        let conversations = value.as_array().expect("starts with '['");
+        return conversations
+            .iter()
+            .enumerate()
+            .map(|(index, conversation)| {
+                from_chatgpt(conversation).context(format!("Failed to import conversation {}", index))
+            })
+            .collect();
+    }
+
+    let lines: Vec<(usize, Value)> = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map(|value| (index + 1, value))
+                .context(format!("Line {} is not valid JSON", index + 1))
+        })
+        .collect::<Result<_>>()?;
+
+    match lines.first() {
+        None => Ok(Vec::new()),
+        Some((_, first)) if first.get("mapping").is_some() => lines
+            .iter()
+            .map(|(line, value)| from_chatgpt(value).context(format!("Failed to import line {}", line)))
+            .collect(),
+        Some((_, first)) if first.get("messages").is_some() => lines
+            .iter()
+            .map(|(line, value)| from_openai(value).context(format!("Failed to import line {}", line)))
+            .collect(),
+        Some((_, first)) if first.get("role").is_some() => Ok(vec![from_message_lines(&lines)?]),
+        Some(_) => Err(anyhow!(
+            "Unrecognised format; expected ChatGPT conversations.json or OpenAI-format JSONL"
+        )),
+    }
+}
+
+fn new_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// The ID given in an export, if it is a UUID. Anything else is not trusted
+/// as a conversation ID, which stores use in file names.
+fn imported_id(id: Option<&str>) -> Option<String> {
+    uuid::Uuid::parse_str(id?).ok().map(|uuid| uuid.hyphenated().to_string())
+}
+
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    let seconds = value.as_f64()?;
+    DateTime::from_timestamp(seconds.trunc() as i64, (seconds.fract() * 1e9) as u32)
+}
+
+/// Text of a message whose content is a string, an array of parts (strings
+/// or `{"type": "text", "text": ...}` objects) or null.
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.as_str().or_else(|| part["text"].as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Builds a conversation, turning a leading system message into the system
+/// prompt.
+fn conversation_from(
+    title: Option<String>,
+    mut messages: Vec<Message>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+) -> Conversation {
+    let (_, mut conversation) = Conversation::new(String::new());
+
+    if messages.first().is_some_and(|message| message.role == Role::System) {
+        conversation.system_prompt = Some(messages.remove(0).content);
+    }
+
+    conversation.title = title
+        .filter(|title| !title.trim().is_empty())
+        .or_else(|| title::heuristic_title(&messages))
+        .unwrap_or_else(|| "Imported conversation".to_string());
+    conversation.created_at = created_at
+        .or_else(|| messages.first().map(|message| message.timestamp))
+        .unwrap_or(conversation.created_at);
+    conversation.updated_at = updated_at
+        .or_else(|| messages.last().map(|message| message.timestamp))
+        .unwrap_or(conversation.created_at);
+    conversation.messages = messages;
+
+    conversation
+}
+
+/// A ChatGPT export stores each conversation as a tree of edits and
+/// regenerations; the visible thread is the path from `current_node` back
+/// to the root. Hidden and tool messages are dropped, as they have no
+/// equivalent that providers would accept.
+fn from_chatgpt(value: &Value) -> Result<(String, Conversation)> {
+    let mapping = value["mapping"]
+        .as_object()
+        .ok_or_else(|| anyhow!("Missing 'mapping'"))?;
+
+    let mut node_id = value["current_node"]
+        .as_str()
+        .map(str::to_string)
+        .or_else(|| {
+            // Without a current node, follow the last child down from the root.
+            let mut id = mapping.iter().find(|(_, node)| node["parent"].is_null())?.0.clone();
+            while let Some(child) = mapping.get(&id)?["children"].as_array().and_then(|c| c.last()) {
+                id = child.as_str()?.to_string();
+            }
+            Some(id)
+        });
+
+    let mut thread = Vec::new();
+    let mut seen = HashSet::new();
+    while let Some(id) = node_id {
+        if !seen.insert(id.clone()) {
+            return Err(anyhow!("Message tree has a cycle at node {}", id));
+        }
+        let node = mapping.get(&id).ok_or_else(|| anyhow!("Missing node {}", id))?;
+        thread.push(node);
+        node_id = node["parent"].as_str().map(str::to_string);
+    }
+    thread.reverse();
+
+    let mut messages = Vec::new();
+    for node in thread {
+        let message = &node["message"];
+        if message.is_null() || message["metadata"]["is_visually_hidden_from_conversation"] == true {
+            continue;
+        }
+
+        let role = match message["author"]["role"].as_str() {
+            Some("user") => Role::User,
+            Some("assistant") => Role::Assistant,
+            Some("system") => Role::System,
+            _ => continue,
+        };
+
+        let content = &message["content"];
+        let text = match content["content_type"].as_str() {
+            Some("text") | Some("multimodal_text") => text_of(&content["parts"]),
+            Some("code") => content["text"].as_str().unwrap_or_default().to_string(),
+            _ => continue,
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let mut imported = Message::new(role, text);
+        if let Some(created) = timestamp(&message["create_time"]) {
+            imported.timestamp = created;
+        }
+        if role == Role::Assistant {
+            imported.model = message["metadata"]["model_slug"].as_str().map(str::to_string);
+        }
+        messages.push(imported);
+    }
+
+    let id = imported_id(value["conversation_id"].as_str().or_else(|| value["id"].as_str())).unwrap_or_else(new_id);
+
+    let conversation = conversation_from(
+        value["title"].as_str().map(str::to_string),
+        messages,
+        timestamp(&value["create_time"]),
+        timestamp(&value["update_time"]),
+    );
+
+    Ok((id, conversation))
+}
+
+/// One `{"messages": [...]}` line in OpenAI's chat or fine-tuning format.
+fn from_openai(value: &Value) -> Result<(String, Conversation)> {
+    let entries = value["messages"]
+        .as_array()
+        .ok_or_else(|| anyhow!("'messages' is not an array"))?;
+
+    let mut messages = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let role: Role = entry["role"]
+            .as_str()
+            .unwrap_or_default()
+            .parse()
+            .context(format!("Message {} has an invalid role", index))?;
+
+        let tool_calls = entry["tool_calls"]
+            .as_array()
+            .map(|calls| {
+                calls
+                    .iter()
+                    .map(|call| ToolCall {
+                        id: call["id"].as_str().unwrap_or_default().to_string(),
+                        name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: call["function"]["arguments"].as_str().unwrap_or("{}").to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        messages.push(Message {
+            tool_calls,
+            tool_call_id: entry["tool_call_id"].as_str().map(str::to_string),
+            ..Message::new(role, text_of(&entry["content"]))
+        });
+    }
+
+    Ok((new_id(), conversation_from(None, messages, None, None)))
+}
+
+/// Lines of stored messages, as written by `spi chat export --format jsonl`.
+fn from_message_lines(lines: &[(usize, Value)]) -> Result<(String, Conversation)> {
+    let messages = lines
+        .iter()
+        .map(|(line, value)| {
+            serde_json::from_value(value.clone()).context(format!("Line {} is not a valid message", line))
+        })
+        .collect::<Result<Vec<Message>>>()?;
+
+    let mut conversation = conversation_from(None, messages, None, None);
+    for usage in conversation.messages.iter().filter_map(|message| message.usage) {
+        conversation.usage += usage;
+    }
+
+    Ok((new_id(), conversation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::export::{export, ExportFormat};
+    use serde_json::json;
+
+    #[test]
+    fn test_imports_chatgpt_current_branch() {
+        let export = json!([{
+            "title": "Rust lifetimes",
+            "create_time": 1700000000.5,
+            "update_time": 1700000100.0,
+            "conversation_id": "6f1c2d3e-0000-4000-8000-000000000001",
+            "current_node": "c",
+            "mapping": {
+                "root": {"id": "root", "message": null, "parent": null, "children": ["sys"]},
+                "sys": {"id": "sys", "parent": "root", "children": ["a"], "message": {
+                    "author": {"role": "system"}, "content": {"content_type": "text", "parts": [""]},
+                    "metadata": {"is_visually_hidden_from_conversation": true}}},
+                "a": {"id": "a", "parent": "sys", "children": ["b", "b2"], "message": {
+                    "author": {"role": "user"}, "create_time": 1700000001.0,
+                    "content": {"content_type": "text", "parts": ["What is 'a?"]}}},
+                "b2": {"id": "b2", "parent": "a", "children": [], "message": {
+                    "author": {"role": "assistant"}, "content": {"content_type": "text", "parts": ["old answer"]}}},
+                "b": {"id": "b", "parent": "a", "children": ["c"], "message": {
+                    "author": {"role": "assistant"}, "metadata": {"model_slug": "gpt-4o"},
+                    "content": {"content_type": "text", "parts": ["A lifetime."]}}},
+                "c": {"id": "c", "parent": "b", "children": [], "message": {
+                    "author": {"role": "user"}, "content": {"content_type": "text", "parts": ["Thanks"]}}}
+            }
+        }]);
+
+        let imported = import(&export.to_string()).unwrap();
+        assert_eq!(imported.len(), 1);
+        let (id, conversation) = &imported[0];
+        assert_eq!(id, "6f1c2d3e-0000-4000-8000-000000000001");
+        assert_eq!(conversation.title, "Rust lifetimes");
+        assert_eq!(conversation.created_at.timestamp(), 1700000000);
+        assert_eq!(conversation.updated_at.timestamp(), 1700000100);
+
+        let contents: Vec<&str> = conversation.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["What is 'a?", "A lifetime.", "Thanks"]);
+        assert_eq!(conversation.messages[0].timestamp.timestamp(), 1700000001);
+        assert_eq!(conversation.messages[1].model.as_deref(), Some("gpt-4o"));
+    }
+
+    #[test]
+    fn test_replaces_ids_that_are_not_uuids() {
+        let conversation = |id: &str| json!({"conversation_id": id, "mapping": {}});
+        let export = json!([
+            conversation("../../.bashrc"),
+            conversation("/etc/passwd"),
+            conversation("6F1C2D3E-0000-4000-8000-000000000001"),
+        ]);
+
+        let imported = import(&export.to_string()).unwrap();
+        for (id, _) in &imported[..2] {
+            assert!(uuid::Uuid::parse_str(id).is_ok() && !id.contains('/'));
+        }
+        assert_eq!(imported[2].0, "6f1c2d3e-0000-4000-8000-000000000001");
+    }
+
+    #[test]
+    fn test_imports_openai_jsonl_and_own_export() {
+        let jsonl = concat!(
+            r#"{"messages": [{"role": "system", "content": "Be brief."}, {"role": "user", "content": "Hi there"}, {"role": "assistant", "content": "Hello"}]}"#,
+            "\n\n",
+            r#"{"messages": [{"role": "user", "content": [{"type": "text", "text": "Second"}]}]}"#,
+        );
+
+        let imported = import(jsonl).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].1.system_prompt.as_deref(), Some("Be brief."));
+        assert_eq!(imported[0].1.title, "Hi there");
+        assert_eq!(imported[0].1.messages.len(), 2);
+        assert_eq!(imported[1].1.messages[0].content, "Second");
+
+        let exported = export(&imported[0].1, ExportFormat::Jsonl).unwrap();
+        let round_trip = import(&exported).unwrap();
+        assert_eq!(round_trip[0].1.messages.len(), 2);
+        assert_eq!(round_trip[0].1.messages[1].role, Role::Assistant);
+
+        assert!(import(r#"{"role": "narrator"}"#).is_err());
+        assert!(import(r#"{"something": "else"}"#).is_err());
+    }
+}
@@ -1,4 +1,14 @@
 // Copyright (c) 2025 SharPi Contributors
 // MIT License
 
+pub mod context;
+pub mod crypto;
+pub mod export;
 pub mod history;
+pub mod import;
+pub mod retention;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
+pub mod title;
+pub mod usage;
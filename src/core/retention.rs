@@ -0,0 +1,264 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::config::HistoryConfig;
+use crate::core::history::{ConversationMetadata, History, ListOptions};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use std::str::FromStr;
+
+/// What `spi chat gc` does with conversations past the retention limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireAction {
+    /// Hide them from listings; nothing is lost.
+    Archive,
+    Delete,
+}
+
+impl FromStr for ExpireAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "archive" => Ok(ExpireAction::Archive),
+            "delete" => Ok(ExpireAction::Delete),
+            _ => Err(anyhow!("Unknown expire action '{}'; expected \"archive\" or \"delete\"", s)),
+        }
+    }
+}
+
+/// Limits on how much history is kept, from the `[history]` config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_conversations: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub action: ExpireAction,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &HistoryConfig) -> Result<Self> {
+        Ok(Self {
+            max_age: config.max_age_days.map(|days| Duration::days(days.into())),
+            max_conversations: config.max_conversations,
+            max_bytes: config.max_bytes,
+            action: config.expire.parse().context("Invalid 'expire' in [history] config")?,
+        })
+    }
+
+    /// Whether no limit is set, so nothing can expire.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none() && self.max_conversations.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// A stored conversation as seen by the retention policy.
+#[derive(Debug, Clone)]
+pub struct Retained {
+    pub id: String,
+    pub metadata: ConversationMetadata,
+    pub size: u64,
+}
+
+/// Picks the conversations past the policy's limits.
+///
+/// Conversations are counted newest first (by last update), and a
+/// conversation expires if it is too old or comes after the count or size
+/// limit was reached. Pinned conversations and the active one never expire,
+/// but do count toward the limits. When archiving, conversations that are
+/// archived already are left out entirely.
+pub fn expired<'a>(
+    conversations: &'a [Retained],
+    policy: &RetentionPolicy,
+    active_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Vec<&'a Retained> {
+    let mut considered: Vec<&Retained> = conversations
+        .iter()
+        .filter(|conversation| policy.action == ExpireAction::Delete || !conversation.metadata.archived)
+        .collect();
+    considered.sort_by_key(|conversation| std::cmp::Reverse(conversation.metadata.updated_at));
+
+    let mut bytes = 0;
+    let mut expired = Vec::new();
+
+    for (newer, conversation) in considered.into_iter().enumerate() {
+        bytes += conversation.size;
+
+        let protected = conversation.metadata.pinned || active_id == Some(conversation.id.as_str());
+        let too_old = policy.max_age.is_some_and(|max_age| now - conversation.metadata.updated_at > max_age);
+        let too_many = policy.max_conversations.is_some_and(|max| newer >= max);
+        let too_big = policy.max_bytes.is_some_and(|max| bytes > max);
+
+        if !protected && (too_old || too_many || too_big) {
+            expired.push(conversation);
+        }
+    }
+
+    expired
+}
+
+/// What `collect_garbage` did, or would do.
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    pub expired: Vec<Retained>,
+    /// Set if the active conversation ID pointed at a conversation that no
+    /// longer exists, and was cleared.
+    pub cleared_dangling_active: bool,
+}
+
+impl GcReport {
+    pub fn bytes(&self) -> u64 {
+        self.expired.iter().map(|conversation| conversation.size).sum()
+    }
+}
+
+/// Applies `policy` to `history`: clears an active conversation ID that
+/// points at a deleted conversation, then archives or deletes expired
+/// conversations. With `dry_run`, only reports what would be done.
+pub fn collect_garbage(history: &mut History, policy: &RetentionPolicy, dry_run: bool) -> Result<GcReport> {
+    let mut report = GcReport::default();
+
+    if let Some(active_id) = history.active_conversation_id.clone() {
+        if !history.store().conversation_exists(&active_id)? {
+            report.cleared_dangling_active = true;
+            if !dry_run {
+                info!("Clearing active conversation {}, which no longer exists", active_id);
+                history.active_conversation_id = None;
+                history.save()?;
+            }
+        }
+    }
+
+    if policy.is_unlimited() {
+        return Ok(report);
+    }
+
+    let options = ListOptions {
+        include_archived: true,
+        ..ListOptions::default()
+    };
+    let mut conversations = Vec::new();
+    for (id, metadata) in history.list_conversations(&options)? {
+        let size = history.store().conversation_size(&id)?;
+        conversations.push(Retained { id, metadata, size });
+    }
+
+    let active_id = if report.cleared_dangling_active { None } else { history.active_conversation_id.clone() };
+    report.expired = expired(&conversations, policy, active_id.as_deref(), Utc::now())
+        .into_iter()
+        .cloned()
+        .collect();
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    for conversation in &report.expired {
+        match policy.action {
+            ExpireAction::Archive => {
+                let _lock = history.lock_conversation(&conversation.id)?;
+                let mut stored = history.get_conversation(&conversation.id)?;
+                stored.archived = true;
+                history.save_conversation(&conversation.id, &stored)?;
+            },
+            ExpireAction::Delete => history.remove_conversation(&conversation.id)?,
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::Usage;
+    use crate::core::store::{HistoryStore, MemoryStore};
+
+    fn conversation(id: &str, days_old: i64, size: u64, now: DateTime<Utc>) -> Retained {
+        let updated_at = now - Duration::days(days_old);
+        Retained {
+            id: id.to_string(),
+            metadata: ConversationMetadata {
+                title: id.to_string(),
+                message_count: 1,
+                created_at: updated_at,
+                updated_at,
+                usage: Usage::default(),
+                tags: Vec::new(),
+                pinned: false,
+                archived: false,
+            },
+            size,
+        }
+    }
+
+    fn ids(expired: Vec<&Retained>) -> Vec<&str> {
+        expired.into_iter().map(|conversation| conversation.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_expiry_by_age_count_and_size_spares_pinned_and_active() {
+        let now = Utc::now();
+        let mut conversations = vec![
+            conversation("new", 1, 100, now),
+            conversation("mid", 10, 100, now),
+            conversation("old", 100, 100, now),
+            conversation("ancient", 400, 100, now),
+        ];
+        let policy = |max_age_days: Option<i64>, max_conversations, max_bytes| RetentionPolicy {
+            max_age: max_age_days.map(Duration::days),
+            max_conversations,
+            max_bytes,
+            action: ExpireAction::Archive,
+        };
+
+        assert_eq!(ids(expired(&conversations, &policy(Some(30), None, None), None, now)), ["old", "ancient"]);
+        assert_eq!(ids(expired(&conversations, &policy(None, Some(1), None), None, now)), ["mid", "old", "ancient"]);
+        assert_eq!(ids(expired(&conversations, &policy(None, None, Some(250)), None, now)), ["old", "ancient"]);
+        assert!(expired(&conversations, &policy(None, None, None), None, now).is_empty());
+
+        // Protected conversations still count toward the limits.
+        conversations[2].metadata.pinned = true;
+        assert_eq!(ids(expired(&conversations, &policy(None, Some(1), None), Some("mid"), now)), ["ancient"]);
+
+        // Archived conversations are already out of the way when archiving,
+        // but are deleted like any other.
+        conversations[3].metadata.archived = true;
+        assert!(expired(&conversations, &policy(Some(30), None, None), None, now).is_empty());
+        let delete = RetentionPolicy { action: ExpireAction::Delete, ..policy(Some(30), None, None) };
+        assert_eq!(ids(expired(&conversations, &delete, None, now)), ["ancient"]);
+    }
+
+    #[test]
+    fn test_gc_clears_dangling_active_conversation() {
+        let store = MemoryStore::new();
+        let mut history = History::open(Box::new(store.clone())).unwrap();
+
+        let (old, mut conversation) = history.create_conversation("Old".to_string()).unwrap();
+        conversation.updated_at = Utc::now() - Duration::days(60);
+        history.save_conversation(&old, &conversation).unwrap();
+
+        // Deleted behind History's back, as by removing its file by hand.
+        let (gone, _) = history.create_conversation("Gone".to_string()).unwrap();
+        store.delete_conversation(&gone).unwrap();
+
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::days(30)),
+            max_conversations: None,
+            max_bytes: None,
+            action: ExpireAction::Delete,
+        };
+
+        let report = collect_garbage(&mut history, &policy, true).unwrap();
+        assert!(report.cleared_dangling_active);
+        assert_eq!(report.expired[0].id, old);
+        assert_eq!(store.load_active_id().unwrap(), Some(gone));
+        assert!(store.conversation_exists(&old).unwrap());
+
+        collect_garbage(&mut history, &policy, false).unwrap();
+        assert_eq!(store.load_active_id().unwrap(), None);
+        assert!(!store.conversation_exists(&old).unwrap());
+    }
+}
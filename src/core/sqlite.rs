@@ -0,0 +1,418 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::clients::Usage;
+use crate::core::history::{self, Conversation, ConversationMetadata, SearchHit, CURRENT_SCHEMA_VERSION};
+use crate::core::store::{self, ConversationLock, HistoryStore};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    message_count INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    total_tokens INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    -- JSON array
+    tags TEXT NOT NULL DEFAULT '[]',
+    pinned INTEGER NOT NULL DEFAULT 0,
+    archived INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS state (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);
+
+-- One row per message, plus one with a NULL message_index for the title.
+CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(
+    conversation_id UNINDEXED,
+    message_index UNINDEXED,
+    content
+);
+";
+
+/// Columns added to `conversations` after its first version, with their
+/// definitions. Databases created before are given them on opening; no
+/// existing conversation can have set them yet, so the defaults are right.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("tags", "TEXT NOT NULL DEFAULT '[]'"),
+    ("pinned", "INTEGER NOT NULL DEFAULT 0"),
+    ("archived", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// Keeps conversations in an SQLite database, with an FTS5 index over
+/// titles and message content.
+///
+/// Each conversation is stored whole as JSON, in the same format as the
+/// JSON store, alongside the columns needed to list conversations without
+/// parsing them.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+    /// Where conversation lock files go; `None` for in-memory databases,
+    /// which no other process can see.
+    locks_dir: Option<PathBuf>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context(format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let connection = Connection::open(path)
+            .context(format!("Failed to open history database: {}", path.display()))?;
+        let locks_dir = path.parent().unwrap_or(Path::new(".")).join("locks");
+        Self::with_connection(connection, Some(locks_dir))
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, None)
+    }
+
+    fn with_connection(connection: Connection, locks_dir: Option<PathBuf>) -> Result<Self> {
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create history database schema")?;
+
+        let existing: Vec<String> = connection
+            .prepare("SELECT name FROM pragma_table_info('conversations')")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (column, definition) in ADDED_COLUMNS {
+            if !existing.iter().any(|name| name == column) {
+                connection
+                    .execute_batch(&format!("ALTER TABLE conversations ADD COLUMN {} {}", column, definition))
+                    .context(format!("Failed to add column '{}' to the history database", column))?;
+            }
+        }
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            locks_dir,
+        })
+    }
+
+    fn delete_rows(&self, id: &str) -> Result<()> {
+        let mut connection = self.connection();
+        let tx = connection.transaction()?;
+
+        if tx.execute("DELETE FROM conversations WHERE id = ?1", [id])? == 0 {
+            return Err(anyhow!("Conversation with ID {} does not exist", id));
+        }
+        tx.execute("DELETE FROM search_index WHERE conversation_id = ?1", [id])?;
+
+        tx.commit().context("Failed to delete conversation")
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Turns free text into an FTS5 query matching rows that contain every
+/// word (or a word starting with it). Each word is quoted so punctuation in
+/// the input cannot break the query syntax.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc))
+}
+
+impl HistoryStore for SqliteStore {
+    /// Rows written by older versions are upgraded in place.
+    fn load_conversation(&self, id: &str) -> Result<Conversation> {
+        let data: Option<String> = self
+            .connection()
+            .query_row("SELECT data FROM conversations WHERE id = ?1", [id], |row| row.get(0))
+            .optional()?;
+        let data = data.ok_or_else(|| anyhow!("Conversation with ID {} does not exist", id))?;
+
+        let (conversation, migrated_from) = history::parse_conversation(&data)
+            .context(format!("Failed to load conversation {}", id))?;
+
+        if let Some(version) = migrated_from {
+            info!("Upgraded conversation {} from schema version {} to {}", id, version, CURRENT_SCHEMA_VERSION);
+            self.save_conversation(id, &conversation)?;
+        }
+
+        Ok(conversation)
+    }
+
+    fn save_conversation(&self, id: &str, conversation: &Conversation) -> Result<()> {
+        let data = serde_json::to_string(conversation)
+            .context("Failed to serialize conversation to JSON")?;
+
+        let mut connection = self.connection();
+        let tx = connection.transaction()?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO conversations
+                (id, title, created_at, updated_at, message_count, prompt_tokens, completion_tokens, total_tokens, data,
+                 tags, pinned, archived)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                id,
+                conversation.title,
+                conversation.created_at.to_rfc3339(),
+                conversation.updated_at.to_rfc3339(),
+                conversation.messages.len() as i64,
+                conversation.usage.prompt_tokens as i64,
+                conversation.usage.completion_tokens as i64,
+                conversation.usage.total_tokens as i64,
+                data,
+                serde_json::to_string(&conversation.tags)?,
+                conversation.pinned,
+                conversation.archived,
+            ],
+        )?;
+
+        tx.execute("DELETE FROM search_index WHERE conversation_id = ?1", [id])?;
+        tx.execute(
+            "INSERT INTO search_index (conversation_id, message_index, content) VALUES (?1, NULL, ?2)",
+            params![id, conversation.title],
+        )?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO search_index (conversation_id, message_index, content) VALUES (?1, ?2, ?3)",
+            )?;
+            for (index, message) in conversation.messages.iter().enumerate() {
+                if !message.content.is_empty() {
+                    insert.execute(params![id, index as i64, message.content])?;
+                }
+            }
+        }
+
+        tx.commit().context("Failed to save conversation")?;
+        Ok(())
+    }
+
+    fn delete_conversation(&self, id: &str) -> Result<()> {
+        let lock = self.lock_conversation(id)?;
+        let deleted = self.delete_rows(id);
+        lock.remove()?;
+        deleted
+    }
+
+    fn conversation_exists(&self, id: &str) -> Result<bool> {
+        let exists = self
+            .connection()
+            .query_row("SELECT 1 FROM conversations WHERE id = ?1", [id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        Ok(exists)
+    }
+
+    fn conversation_ids(&self) -> Result<Vec<String>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT id FROM conversations")?;
+        let ids = statement.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    fn load_active_id(&self) -> Result<Option<String>> {
+        let value: Option<Option<String>> = self
+            .connection()
+            .query_row("SELECT value FROM state WHERE key = 'active_conversation_id'", [], |row| row.get(0))
+            .optional()?;
+        Ok(value.flatten())
+    }
+
+    fn save_active_id(&self, id: Option<&str>) -> Result<()> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO state (key, value) VALUES ('active_conversation_id', ?1)",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    fn conversation_size(&self, id: &str) -> Result<u64> {
+        let size: Option<i64> = self
+            .connection()
+            .query_row("SELECT length(CAST(data AS BLOB)) FROM conversations WHERE id = ?1", [id], |row| row.get(0))
+            .optional()?;
+        size.map(|size| size as u64)
+            .ok_or_else(|| anyhow!("Conversation with ID {} does not exist", id))
+    }
+
+    /// Locks `locks/<id>.lock` next to the database file.
+    fn lock_conversation(&self, id: &str) -> Result<ConversationLock> {
+        match &self.locks_dir {
+            Some(dir) => store::lock_file(dir, id),
+            None => Ok(ConversationLock::unlocked()),
+        }
+    }
+
+    /// Reads the metadata columns only; no conversation is parsed.
+    fn list_metadata(&self) -> Result<Vec<(String, ConversationMetadata)>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT id, title, message_count, created_at, updated_at, prompt_tokens, completion_tokens,
+                    total_tokens, tags, pinned, archived
+             FROM conversations",
+        )?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, i64>(6)?,
+                    row.get::<_, i64>(7)?,
+                    row.get::<_, String>(8)?,
+                    row.get::<_, bool>(9)?,
+                    row.get::<_, bool>(10)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(|(id, title, message_count, created_at, updated_at, prompt_tokens, completion_tokens, total_tokens, tags, pinned, archived)| {
+                let metadata = ConversationMetadata {
+                    title,
+                    message_count: message_count as usize,
+                    created_at: parse_timestamp(&created_at)?,
+                    updated_at: parse_timestamp(&updated_at)?,
+                    usage: Usage {
+                        prompt_tokens: prompt_tokens as u64,
+                        completion_tokens: completion_tokens as u64,
+                        total_tokens: total_tokens as u64,
+                    },
+                    tags: serde_json::from_str(&tags).context(format!("Invalid tags for conversation {}", id))?,
+                    pinned,
+                    archived,
+                };
+                Ok((id, metadata))
+            })
+            .collect()
+    }
+
+    /// Uses the FTS5 index, best matches first.
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let query = fts_query(query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT search_index.conversation_id, conversations.title, search_index.message_index,
+                    snippet(search_index, 2, '[', ']', '…', 12)
+             FROM search_index
+             JOIN conversations ON conversations.id = search_index.conversation_id
+             WHERE search_index MATCH ?1
+             ORDER BY rank
+             LIMIT ?2",
+        )?;
+
+        let hits = statement
+            .query_map(params![query, limit as i64], |row| {
+                Ok(SearchHit {
+                    conversation_id: row.get(0)?,
+                    title: row.get(1)?,
+                    message_index: row.get::<_, Option<i64>>(2)?.map(|index| index as usize),
+                    snippet: row.get::<_, String>(3)?.replace('\n', " "),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .context("Search failed")?;
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_store_lists_and_searches() {
+        let store = SqliteStore::open_in_memory().unwrap();
+
+        let (id, mut conversation) = Conversation::new("Deploy notes".to_string());
+        conversation.add_user_message("How do I roll back a \"blue-green\" deployment?".to_string());
+        conversation.add_assistant_message("Switch the router back to the blue pool.".to_string());
+        store.save_conversation(&id, &conversation).unwrap();
+        store.save_active_id(Some(&id)).unwrap();
+
+        assert_eq!(store.load_active_id().unwrap(), Some(id.clone()));
+        assert_eq!(store.load_conversation(&id).unwrap().messages.len(), 2);
+
+        conversation.add_tag("ops");
+        conversation.pinned = true;
+        // The total is stored as reported, not recomputed from the parts.
+        conversation.usage = Usage { prompt_tokens: 10, completion_tokens: 5, total_tokens: 40 };
+        store.save_conversation(&id, &conversation).unwrap();
+
+        let listed = store.list_metadata().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].1.title, "Deploy notes");
+        assert_eq!(listed[0].1.message_count, 2);
+        assert_eq!(listed[0].1.tags, ["ops"]);
+        assert!(listed[0].1.pinned && !listed[0].1.archived);
+        assert_eq!(listed[0].1.usage, conversation.usage);
+
+        let hits = store.search("router blue", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_index, Some(1));
+        assert!(hits[0].snippet.contains("[router]"));
+
+        assert_eq!(store.search("deploy", 10).unwrap()[0].message_index, None);
+        assert_eq!(store.search("deployment roll", 10).unwrap().len(), 1);
+        assert_eq!(store.search("depl", 10).unwrap().len(), 2);
+        assert_eq!(store.search("\"blue-green", 10).unwrap().len(), 1);
+
+        // Re-saving replaces the indexed content rather than adding to it.
+        conversation.messages.pop();
+        store.save_conversation(&id, &conversation).unwrap();
+        assert!(store.search("router", 10).unwrap().is_empty());
+
+        store.delete_conversation(&id).unwrap();
+        assert!(store.conversation_ids().unwrap().is_empty());
+        assert!(store.search("deploy", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sqlite_store_adds_new_columns_to_old_databases() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE conversations (
+                    id TEXT PRIMARY KEY, title TEXT NOT NULL, created_at TEXT NOT NULL,
+                    updated_at TEXT NOT NULL, message_count INTEGER NOT NULL, prompt_tokens INTEGER NOT NULL,
+                    completion_tokens INTEGER NOT NULL, total_tokens INTEGER NOT NULL, data TEXT NOT NULL
+                );
+                INSERT INTO conversations VALUES
+                    ('old', 'Old', '2025-01-01T00:00:00+00:00', '2025-01-01T00:00:00+00:00', 0, 0, 0, 0, '{}');",
+            )
+            .unwrap();
+
+        let store = SqliteStore::with_connection(connection, None).unwrap();
+        let listed = store.list_metadata().unwrap();
+        assert_eq!(listed[0].1.title, "Old");
+        assert!(listed[0].1.tags.is_empty() && !listed[0].1.pinned && !listed[0].1.archived);
+    }
+}
@@ -0,0 +1,959 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::config::HistoryConfig;
+use crate::core::crypto::{self, Cipher};
+use crate::core::history::{self, Conversation, ConversationMetadata, SearchHit, CURRENT_SCHEMA_VERSION};
+use anyhow::{anyhow, Context, Result};
+use fs4::fs_std::FileExt;
+use log::{info, warn};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Where conversations and the active conversation ID are kept.
+///
+/// `History` does all of its reading and writing through this trait, so the
+/// library can be embedded with storage other than `~/.sharpi`.
+pub trait HistoryStore: Send + Sync {
+    /// Loads a conversation, failing if it does not exist.
+    fn load_conversation(&self, id: &str) -> Result<Conversation>;
+
+    /// Creates or replaces a conversation.
+    fn save_conversation(&self, id: &str, conversation: &Conversation) -> Result<()>;
+
+    fn delete_conversation(&self, id: &str) -> Result<()>;
+
+    fn conversation_exists(&self, id: &str) -> Result<bool>;
+
+    /// IDs of all stored conversations, in no particular order.
+    fn conversation_ids(&self) -> Result<Vec<String>>;
+
+    fn load_active_id(&self) -> Result<Option<String>>;
+
+    fn save_active_id(&self, id: Option<&str>) -> Result<()>;
+
+    /// Bytes a conversation takes up in the store. The default measures it
+    /// serialized as JSON.
+    fn conversation_size(&self, id: &str) -> Result<u64> {
+        Ok(serde_json::to_vec(&self.load_conversation(id)?)?.len() as u64)
+    }
+
+    /// Takes an exclusive lock on a conversation, waiting for other
+    /// processes to release theirs. Hold it from loading a conversation
+    /// until the changed version is saved, so that concurrent changes are
+    /// applied one after the other instead of overwriting each other. The
+    /// default takes no lock, which suits stores used by a single process.
+    fn lock_conversation(&self, _id: &str) -> Result<ConversationLock> {
+        Ok(ConversationLock::unlocked())
+    }
+
+    /// Metadata for every conversation. The default loads each one in full;
+    /// stores that keep metadata separately should override it.
+    fn list_metadata(&self) -> Result<Vec<(String, ConversationMetadata)>> {
+        Ok(load_all(self)
+            .into_iter()
+            .map(|(id, conversation)| (id, ConversationMetadata::from_conversation(&conversation)))
+            .collect())
+    }
+
+    /// Finds titles and messages containing every word of `query`, most
+    /// recently updated conversations first. The default scans every
+    /// conversation; stores with an index should override it.
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let mut conversations = load_all(self);
+        conversations.sort_by_key(|(_, conversation)| std::cmp::Reverse(conversation.updated_at));
+
+        let mut hits = Vec::new();
+        for (id, conversation) in conversations {
+            let hit = |message_index, snippet| SearchHit {
+                conversation_id: id.clone(),
+                title: conversation.title.clone(),
+                message_index,
+                snippet,
+            };
+
+            if let Some(snippet) = match_snippet(&conversation.title, query) {
+                hits.push(hit(None, snippet));
+            }
+            for (index, message) in conversation.messages.iter().enumerate() {
+                if let Some(snippet) = match_snippet(&message.content, query) {
+                    hits.push(hit(Some(index), snippet));
+                }
+            }
+        }
+
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
+
+/// Loads every conversation in `store`, skipping (with a warning) ones that
+/// fail to load.
+pub(crate) fn load_all<S: HistoryStore + ?Sized>(store: &S) -> Vec<(String, Conversation)> {
+    let ids = match store.conversation_ids() {
+        Ok(ids) => ids,
+        Err(err) => {
+            warn!("Could not list conversations: {:#}", err);
+            return Vec::new();
+        },
+    };
+
+    ids.into_iter()
+        .filter_map(|id| match store.load_conversation(&id) {
+            Ok(conversation) => Some((id, conversation)),
+            Err(err) => {
+                warn!("Skipping conversation {}: {:#}", id, err);
+                None
+            },
+        })
+        .collect()
+}
+
+/// Characters of context kept on each side of a match in search snippets.
+const SNIPPET_CONTEXT: usize = 30;
+
+/// If `text` contains every whitespace-separated word of `query` (ignoring
+/// case), returns an excerpt around the first word with the match in
+/// brackets.
+fn match_snippet(text: &str, query: &str) -> Option<String> {
+    let fold = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let chars: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = chars.iter().copied().map(fold).collect();
+
+    let mut first = None;
+    for word in query.split_whitespace() {
+        let word: Vec<char> = word.chars().map(fold).collect();
+        let position = folded.windows(word.len()).position(|window| window == word.as_slice())?;
+        first.get_or_insert((position, word.len()));
+    }
+    let (position, len) = first?;
+
+    let start = position.saturating_sub(SNIPPET_CONTEXT);
+    let end = (position + len + SNIPPET_CONTEXT).min(chars.len());
+    let excerpt = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
+
+    let snippet = format!(
+        "{}{}[{}]{}{}",
+        if start > 0 { "…" } else { "" },
+        excerpt(start..position),
+        excerpt(position..position + len),
+        excerpt(position + len..end),
+        if end < chars.len() { "…" } else { "" },
+    );
+
+    Some(snippet.replace('\n', " "))
+}
+
+/// Opens the store selected by the `[history]` config, rooted at `root`
+/// (normally `~/.sharpi`).
+pub fn open_store(config: &HistoryConfig, root: &Path) -> Result<Box<dyn HistoryStore>> {
+    if config.encryption != "none" && config.backend != "json" {
+        return Err(anyhow!("History encryption is only supported with backend = \"json\""));
+    }
+
+    match config.backend.as_str() {
+        "json" => {
+            let store = JsonDirStore::new(root);
+            Ok(Box::new(match crypto::load_cipher(config, root)? {
+                Some(cipher) => store.with_cipher(cipher),
+                None => store,
+            }))
+        },
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Box::new(crate::core::sqlite::SqliteStore::open(root.join("history.db"))?)),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => Err(anyhow!("This build of SharPi was compiled without SQLite support")),
+        other => Err(anyhow!("Unknown history backend '{}'; expected \"json\" or \"sqlite\"", other)),
+    }
+}
+
+/// Copies every conversation, and the active conversation ID, from one
+/// store to another, replacing conversations with the same ID. Returns how
+/// many conversations were copied.
+///
+/// Fails, before anything is written, if a conversation can't be loaded,
+/// so that no conversation is left behind unnoticed.
+pub fn copy_store(from: &dyn HistoryStore, to: &dyn HistoryStore) -> Result<usize> {
+    let conversations = load_every(from)?;
+
+    for (id, conversation) in &conversations {
+        to.save_conversation(id, conversation)
+            .context(format!("Failed to copy conversation {}", id))?;
+    }
+    to.save_active_id(from.load_active_id()?.as_deref())?;
+
+    Ok(conversations.len())
+}
+
+/// An advisory lock on a conversation, released when dropped. See
+/// `HistoryStore::lock_conversation`.
+#[derive(Debug)]
+pub struct ConversationLock {
+    file: Option<(File, PathBuf)>,
+}
+
+impl ConversationLock {
+    /// A lock that guards nothing, for stores only one process can use.
+    pub fn unlocked() -> Self {
+        Self { file: None }
+    }
+
+    /// Removes the lock file, for when the conversation it guards has been
+    /// deleted. The file is removed before the lock is released; anyone
+    /// still waiting on it then opens a new one.
+    pub(crate) fn remove(self) -> Result<()> {
+        match &self.file {
+            Some((_, path)) => {
+                fs::remove_file(path).context(format!("Failed to delete lock file: {}", path.display()))
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ConversationLock {
+    fn drop(&mut self) {
+        if let Some((file, _)) = &self.file {
+            let _ = FileExt::unlock(file);
+        }
+    }
+}
+
+/// Whether `id` can be a conversation ID. IDs are used in file names, so
+/// only letters, digits, `-` and `_` are allowed; anything else, such as
+/// `../index`, could name a file outside the store.
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn check_id(id: &str) -> Result<()> {
+    if is_valid_id(id) {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid conversation ID '{}'", id))
+    }
+}
+
+/// Locks `<dir>/<id>.lock`, creating it if needed. The file is only a
+/// handle for the lock and stays empty.
+pub(crate) fn lock_file(dir: &Path, id: &str) -> Result<ConversationLock> {
+    check_id(id)?;
+    let path = dir.join(format!("{}.lock", id));
+
+    loop {
+        let file = open_lock_file(&path)?;
+        if !file.try_lock_exclusive()? {
+            info!("Waiting for another process to finish with conversation {}", id);
+            file.lock_exclusive()
+                .context(format!("Failed to lock {}", path.display()))?;
+        }
+
+        // If the file was removed while waiting, its conversation was
+        // deleted; lock a new file, as anyone else would.
+        if path.exists() {
+            return Ok(ConversationLock { file: Some((file, path)) });
+        }
+    }
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create directory: {}", dir.display()))?;
+    }
+
+    File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .context(format!("Failed to open lock file: {}", path.display()))
+}
+
+/// Replaces `path` with `contents` such that readers see either the old
+/// file or the new one, never a partly written one: the data is written
+/// and synced to a temporary file in the same directory, which is then
+/// renamed over `path`.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path.parent().ok_or_else(|| anyhow!("Invalid path: {}", path.display()))?;
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("file");
+    let temp_path = dir.join(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4()));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(anyhow::Error::new(err).context(format!("Failed to write {}", path.display())));
+    }
+
+    Ok(())
+}
+
+/// Loads every conversation in `store`, failing on the first that can't
+/// be loaded.
+fn load_every(store: &dyn HistoryStore) -> Result<Vec<(String, Conversation)>> {
+    store
+        .conversation_ids()?
+        .into_iter()
+        .map(|id| {
+            let conversation = store
+                .load_conversation(&id)
+                .context(format!("Failed to load conversation {}", id))?;
+            Ok((id, conversation))
+        })
+        .collect()
+}
+
+/// Loads every conversation in `from` and saves it to `to`, which may be
+/// the same store, e.g. to encrypt or decrypt it in place. Nothing is
+/// written unless every conversation loads.
+pub fn rewrite_store(from: &dyn HistoryStore, to: &dyn HistoryStore) -> Result<usize> {
+    let conversations = load_every(from)?;
+
+    for (id, conversation) in &conversations {
+        let _lock = to.lock_conversation(id)?;
+        to.save_conversation(id, conversation)?;
+    }
+
+    Ok(conversations.len())
+}
+
+fn not_found(id: &str) -> anyhow::Error {
+    anyhow!("Conversation with ID {} does not exist", id)
+}
+
+/// An entry in the JSON store's metadata index, valid while the
+/// conversation file's modification time is `modified` and its size is
+/// `size`. Modification times alone can miss a change, on filesystems that
+/// keep them coarsely or when a file is written twice in quick succession.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    modified: DateTime<Utc>,
+    #[serde(default)]
+    size: u64,
+    #[serde(flatten)]
+    metadata: ConversationMetadata,
+}
+
+impl IndexEntry {
+    /// The modification time and size of the file at `path`.
+    fn stamp(path: &Path) -> Result<(DateTime<Utc>, u64)> {
+        let metadata = fs::metadata(path)?;
+        Ok((metadata.modified()?.into(), metadata.len()))
+    }
+
+    fn new(path: &Path, conversation: &Conversation) -> Result<Self> {
+        let (modified, size) = Self::stamp(path)?;
+        Ok(Self {
+            modified,
+            size,
+            metadata: ConversationMetadata::from_conversation(conversation),
+        })
+    }
+
+    fn is_current(&self, stamp: (DateTime<Utc>, u64)) -> bool {
+        (self.modified, self.size) == stamp
+    }
+}
+
+/// Stores each conversation as `<root>/conversations/<id>.json` and the
+/// active conversation ID in `<root>/active_conversation.json`.
+///
+/// Listing is served from `<root>/index.json`, which holds each
+/// conversation's metadata. It is updated when a conversation is saved, and
+/// brought up to date whenever it is read by loading only the files that
+/// changed since, so files changed by hand are picked up too.
+///
+/// With a cipher, conversations and the index are written encrypted.
+/// Files are decrypted on loading if they are encrypted, so a store can be
+/// read while only some of it has been encrypted.
+#[derive(Debug, Clone)]
+pub struct JsonDirStore {
+    root: PathBuf,
+    cipher: Option<Cipher>,
+}
+
+impl JsonDirStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            cipher: None,
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Reads a file, decrypting it if it is encrypted.
+    fn read(&self, path: &Path) -> Result<String> {
+        let data = fs::read(path)?;
+
+        let data = if crypto::is_encrypted(&data) {
+            let cipher = self.cipher.as_ref().ok_or_else(|| {
+                anyhow!("{} is encrypted; set encryption under [history] in the config", path.display())
+            })?;
+            cipher.decrypt(&data)?
+        } else {
+            data
+        };
+
+        String::from_utf8(data).context("File is not valid UTF-8")
+    }
+
+    /// Writes a file, encrypted if the store has a cipher.
+    fn write(&self, path: &Path, content: &str) -> Result<()> {
+        match &self.cipher {
+            Some(cipher) => write_atomically(path, &cipher.encrypt(content.as_bytes())),
+            None => write_atomically(path, content.as_bytes()),
+        }
+    }
+
+    /// The store in `~/.sharpi`.
+    pub fn default_location() -> Result<Self> {
+        Ok(Self::new(history::sharpi_dir()?))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn conversations_dir(&self) -> Result<PathBuf> {
+        let conversations_dir = self.root.join("conversations");
+
+        if !conversations_dir.exists() {
+            fs::create_dir_all(&conversations_dir)
+                .context(format!("Failed to create directory: {}", conversations_dir.display()))?;
+        }
+
+        Ok(conversations_dir)
+    }
+
+    fn conversation_path(&self, id: &str) -> Result<PathBuf> {
+        check_id(id)?;
+        Ok(self.conversations_dir()?.join(format!("{}.json", id)))
+    }
+
+    fn active_conversation_path(&self) -> PathBuf {
+        self.root.join("active_conversation.json")
+    }
+
+    fn locks_dir(&self) -> PathBuf {
+        self.root.join("locks")
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.root.join("index.json")
+    }
+
+    /// Locks `<root>/index.lock`. Anything that reads the index to write
+    /// back a changed version holds this, so concurrent updates aren't lost.
+    fn lock_index(&self) -> Result<ConversationLock> {
+        let path = self.root.join("index.lock");
+        let file = open_lock_file(&path)?;
+        file.lock_exclusive()
+            .context(format!("Failed to lock {}", path.display()))?;
+        Ok(ConversationLock { file: Some((file, path)) })
+    }
+
+    fn write_conversation(&self, path: &Path, conversation: &Conversation) -> Result<()> {
+        let json = serde_json::to_string_pretty(conversation)
+            .context("Failed to serialize conversation to JSON")?;
+
+        self.write(path, &json)
+            .context(format!("Failed to write conversation file: {}", path.display()))
+    }
+
+    /// A missing or unreadable index is treated as empty and rebuilt.
+    fn load_index(&self) -> BTreeMap<String, IndexEntry> {
+        let path = self.index_path();
+        if !path.exists() {
+            return BTreeMap::new();
+        }
+        let content = match self.read(&path) {
+            Ok(content) => content,
+            Err(err) => {
+                warn!("Rebuilding conversation index {}: {:#}", path.display(), err);
+                return BTreeMap::new();
+            },
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            warn!("Rebuilding conversation index {}: {}", path.display(), err);
+            BTreeMap::new()
+        })
+    }
+
+    /// The index can always be rebuilt, so failing to save it is only
+    /// worth a warning.
+    fn save_index(&self, index: &BTreeMap<String, IndexEntry>) {
+        let saved = serde_json::to_string(index)
+            .context("Failed to serialize conversation index")
+            .and_then(|json| self.write(&self.index_path(), &json));
+        if let Err(err) = saved {
+            warn!("Could not save conversation index: {:#}", err);
+        }
+    }
+}
+
+impl HistoryStore for JsonDirStore {
+    /// Files written by older versions are upgraded in place. The index is
+    /// left alone, as listing may be holding its lock; the new modification
+    /// time tells the next listing to update it.
+    fn load_conversation(&self, id: &str) -> Result<Conversation> {
+        let path = self.conversation_path(id)?;
+
+        if !path.exists() {
+            return Err(not_found(id));
+        }
+
+        let content = self
+            .read(&path)
+            .context(format!("Failed to read conversation file: {}", path.display()))?;
+
+        let (conversation, migrated_from) = history::parse_conversation(&content)
+            .context(format!("Failed to load conversation file: {}", path.display()))?;
+
+        if let Some(version) = migrated_from {
+            info!("Upgraded conversation {} from schema version {} to {}", id, version, CURRENT_SCHEMA_VERSION);
+            self.write_conversation(&path, &conversation)?;
+        }
+
+        Ok(conversation)
+    }
+
+    fn save_conversation(&self, id: &str, conversation: &Conversation) -> Result<()> {
+        let path = self.conversation_path(id)?;
+        self.write_conversation(&path, conversation)?;
+
+        // Keep the index up to date rather than relying on listing to notice
+        // the change. Only when there is one; listing creates it.
+        if self.index_path().exists() {
+            let _lock = self.lock_index()?;
+            let mut index = self.load_index();
+            index.insert(id.to_string(), IndexEntry::new(&path, conversation)?);
+            self.save_index(&index);
+        }
+
+        Ok(())
+    }
+
+    fn delete_conversation(&self, id: &str) -> Result<()> {
+        let path = self.conversation_path(id)?;
+        let lock = self.lock_conversation(id)?;
+
+        let deleted = fs::remove_file(&path)
+            .context(format!("Failed to delete conversation file: {}", path.display()));
+        lock.remove()?;
+        deleted
+    }
+
+    fn conversation_exists(&self, id: &str) -> Result<bool> {
+        Ok(self.conversation_path(id)?.exists())
+    }
+
+    fn conversation_ids(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+
+        for entry in fs::read_dir(self.conversations_dir()?)? {
+            let path = entry?.path();
+
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn load_active_id(&self) -> Result<Option<String>> {
+        let path = self.active_conversation_path();
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)
+            .context(format!("Failed to read active conversation ID file: {}", path.display()))?;
+
+        serde_json::from_str(&content).context("Failed to parse active conversation ID file")
+    }
+
+    fn save_active_id(&self, id: Option<&str>) -> Result<()> {
+        let path = self.active_conversation_path();
+
+        fs::create_dir_all(&self.root)
+            .context(format!("Failed to create directory: {}", self.root.display()))?;
+
+        let json = serde_json::to_string_pretty(&id)
+            .context("Failed to serialize active conversation ID to JSON")?;
+
+        write_atomically(&path, json.as_bytes())
+            .context(format!("Failed to write active conversation ID file: {}", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Reads the index, loading only conversations whose files are not in
+    /// it or changed since it was written.
+    fn list_metadata(&self) -> Result<Vec<(String, ConversationMetadata)>> {
+        let _lock = self.lock_index()?;
+        let mut index = self.load_index();
+        let mut changed = false;
+
+        let ids = self.conversation_ids()?;
+        for id in &ids {
+            let path = self.conversation_path(id)?;
+
+            let stamp = IndexEntry::stamp(&path)?;
+            if index.get(id).is_some_and(|entry| entry.is_current(stamp)) {
+                continue;
+            }
+
+            match self.load_conversation(id) {
+                Ok(conversation) => {
+                    // Loading may have upgraded and so rewritten the file.
+                    index.insert(id.clone(), IndexEntry::new(&path, &conversation)?);
+                },
+                Err(err) => {
+                    warn!("Skipping conversation {}: {:#}", id, err);
+                    index.remove(id);
+                },
+            }
+            changed = true;
+        }
+
+        let ids: HashSet<String> = ids.into_iter().collect();
+        let before = index.len();
+        index.retain(|id, _| ids.contains(id));
+        changed |= index.len() != before;
+
+        if changed {
+            self.save_index(&index);
+        }
+
+        Ok(index.into_iter().map(|(id, entry)| (id, entry.metadata)).collect())
+    }
+
+    fn conversation_size(&self, id: &str) -> Result<u64> {
+        let path = self.conversation_path(id)?;
+        let metadata = fs::metadata(&path).map_err(|_| not_found(id))?;
+        Ok(metadata.len())
+    }
+
+    /// Locks `<root>/locks/<id>.lock`.
+    fn lock_conversation(&self, id: &str) -> Result<ConversationLock> {
+        lock_file(&self.locks_dir(), id)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    conversations: HashMap<String, Conversation>,
+    active_id: Option<String>,
+}
+
+/// Keeps everything in memory, for tests and short-lived embedding.
+///
+/// Clones share the same contents, so a test can hand one to `History` and
+/// inspect it through another.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        // A panic elsewhere cannot leave the maps half-updated.
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl HistoryStore for MemoryStore {
+    fn load_conversation(&self, id: &str) -> Result<Conversation> {
+        self.state().conversations.get(id).cloned().ok_or_else(|| not_found(id))
+    }
+
+    fn save_conversation(&self, id: &str, conversation: &Conversation) -> Result<()> {
+        self.state().conversations.insert(id.to_string(), conversation.clone());
+        Ok(())
+    }
+
+    fn delete_conversation(&self, id: &str) -> Result<()> {
+        self.state().conversations.remove(id).map(|_| ()).ok_or_else(|| not_found(id))
+    }
+
+    fn conversation_exists(&self, id: &str) -> Result<bool> {
+        Ok(self.state().conversations.contains_key(id))
+    }
+
+    fn conversation_ids(&self) -> Result<Vec<String>> {
+        Ok(self.state().conversations.keys().cloned().collect())
+    }
+
+    fn load_active_id(&self) -> Result<Option<String>> {
+        Ok(self.state().active_id.clone())
+    }
+
+    fn save_active_id(&self, id: Option<&str>) -> Result<()> {
+        self.state().active_id = id.map(str::to_string);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_default_search_and_snippets() {
+        let store = MemoryStore::new();
+        let (id, mut conversation) = Conversation::new("Borrow checker".to_string());
+        conversation.add_user_message("Why does the borrow checker reject this closure?".to_string());
+        conversation.add_assistant_message("Because the closure captures `self` mutably.".to_string());
+        store.save_conversation(&id, &conversation).unwrap();
+
+        let hits = store.search("CLOSURE borrow", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_index, Some(0));
+        assert_eq!(hits[0].snippet, "…he borrow checker reject this [closure]?");
+
+        assert_eq!(store.search("closure", 1).unwrap().len(), 1);
+        assert_eq!(store.search("checker", 10).unwrap()[0].message_index, None);
+        assert!(store.search("lifetime", 10).unwrap().is_empty());
+
+        let long = format!("{}needle{}", "a".repeat(50), "b".repeat(50));
+        let snippet = match_snippet(&long, "needle").unwrap();
+        assert!(snippet.starts_with('…') && snippet.ends_with('…') && snippet.contains("[needle]"));
+    }
+
+    #[test]
+    fn test_json_store_round_trip_and_upgrade() {
+        let root = std::env::temp_dir().join(format!("sharpi-store-{}", Uuid::new_v4()));
+        let store = JsonDirStore::new(&root);
+
+        let (id, mut conversation) = Conversation::new("Stored".to_string());
+        conversation.add_user_message("hello".to_string());
+        store.save_conversation(&id, &conversation).unwrap();
+        store.save_active_id(Some(&id)).unwrap();
+
+        assert_eq!(store.conversation_ids().unwrap(), vec![id.clone()]);
+        assert_eq!(store.load_active_id().unwrap(), Some(id.clone()));
+        assert_eq!(store.load_conversation(&id).unwrap().messages[0].content, "hello");
+
+        let old = r#"{"title": "Old", "created_at": "2025-01-01T00:00:00Z", "updated_at": "2025-01-01T00:00:00Z", "messages": []}"#;
+        fs::write(root.join("conversations/old.json"), old).unwrap();
+        assert_eq!(store.load_conversation("old").unwrap().title, "Old");
+        let upgraded = fs::read_to_string(root.join("conversations/old.json")).unwrap();
+        assert!(upgraded.contains(&format!("\"schema_version\": {}", CURRENT_SCHEMA_VERSION)));
+
+        store.delete_conversation(&id).unwrap();
+        assert!(!store.conversation_exists(&id).unwrap());
+        assert!(!root.join("locks").join(format!("{}.lock", id)).exists());
+        assert!(store.delete_conversation(&id).is_err());
+        assert!(!root.join("locks").join(format!("{}.lock", id)).exists());
+
+        // IDs must not reach files outside the store.
+        fs::write(root.join("index.json"), "{}").unwrap();
+        for id in ["../index", "..", "/etc/passwd", "a\\b", ""] {
+            assert!(store.conversation_exists(id).is_err());
+            assert!(store.delete_conversation(id).is_err());
+            assert!(store.lock_conversation(id).is_err());
+        }
+        assert!(root.join("index.json").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_json_store_index_follows_file_changes() {
+        let root = std::env::temp_dir().join(format!("sharpi-store-{}", Uuid::new_v4()));
+        let store = JsonDirStore::new(&root);
+
+        let (id, mut conversation) = Conversation::new("Indexed".to_string());
+        store.save_conversation(&id, &conversation).unwrap();
+        assert_eq!(store.list_metadata().unwrap()[0].1.message_count, 0);
+        assert!(fs::read_to_string(root.join("index.json")).unwrap().contains("Indexed"));
+
+        // A change made without going through the index, as by another
+        // process or an older version, is noticed by its modification time.
+        conversation.add_user_message("hello".to_string());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(root.join(format!("conversations/{}.json", id)), serde_json::to_string(&conversation).unwrap()).unwrap();
+        assert_eq!(store.list_metadata().unwrap()[0].1.message_count, 1);
+
+        // Nor is a change missed when the modification time stays the same,
+        // as on filesystems that only keep whole seconds.
+        let path = root.join(format!("conversations/{}.json", id));
+        let keep_time = |modified| File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        conversation.title = "Reindexed".to_string();
+        fs::write(&path, serde_json::to_string(&conversation).unwrap()).unwrap();
+        keep_time(modified);
+        assert_eq!(store.list_metadata().unwrap()[0].1.title, "Reindexed");
+        for title in ["Saved: one", "Saved: two"] {
+            conversation.title = title.to_string();
+            store.save_conversation(&id, &conversation).unwrap();
+            keep_time(modified);
+            assert_eq!(store.list_metadata().unwrap()[0].1.title, title);
+        }
+
+        fs::write(root.join("index.json"), "not json").unwrap();
+        assert_eq!(store.list_metadata().unwrap().len(), 1);
+
+        store.delete_conversation(&id).unwrap();
+        assert!(store.list_metadata().unwrap().is_empty());
+        assert_eq!(fs::read_to_string(root.join("index.json")).unwrap(), "{}");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_copy_store_fails_on_unreadable_conversations() {
+        let root = std::env::temp_dir().join(format!("sharpi-store-{}", Uuid::new_v4()));
+        let from = JsonDirStore::new(&root);
+        let (id, conversation) = Conversation::new("Readable".to_string());
+        from.save_conversation(&id, &conversation).unwrap();
+
+        let to = MemoryStore::new();
+        assert_eq!(copy_store(&from, &to).unwrap(), 1);
+
+        fs::write(root.join("conversations/broken.json"), "{").unwrap();
+        let to = MemoryStore::new();
+        let err = copy_store(&from, &to).unwrap_err();
+        assert!(format!("{:#}", err).contains("broken"));
+        assert!(to.conversation_ids().unwrap().is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_json_store() {
+        let root = std::env::temp_dir().join(format!("sharpi-store-{}", Uuid::new_v4()));
+        let plain = JsonDirStore::new(&root);
+        let encrypted = JsonDirStore::new(&root).with_cipher(Cipher::new(&[3; 32]));
+
+        let (id, mut conversation) = Conversation::new("Proprietary".to_string());
+        conversation.add_user_message("fn secret_sauce() {}".to_string());
+        plain.save_conversation(&id, &conversation).unwrap();
+
+        // Plain files stay readable, so a store can be encrypted in place.
+        assert_eq!(rewrite_store(&encrypted, &encrypted).unwrap(), 1);
+        assert_eq!(encrypted.list_metadata().unwrap()[0].1.title, "Proprietary");
+
+        let path = root.join(format!("conversations/{}.json", id));
+        let on_disk = fs::read(&path).unwrap();
+        assert!(crypto::is_encrypted(&on_disk));
+        assert!(!String::from_utf8_lossy(&on_disk).contains("secret_sauce"));
+        assert!(!String::from_utf8_lossy(&fs::read(root.join("index.json")).unwrap()).contains("Proprietary"));
+
+        assert_eq!(encrypted.load_conversation(&id).unwrap().messages[0].content, "fn secret_sauce() {}");
+        assert!(format!("{:#}", plain.load_conversation(&id).unwrap_err()).contains("is encrypted"));
+        let wrong_key = JsonDirStore::new(&root).with_cipher(Cipher::new(&[4; 32]));
+        assert!(rewrite_store(&wrong_key, &plain).is_err());
+
+        rewrite_store(&encrypted, &plain).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("secret_sauce"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers_do_not_lose_messages() {
+        const WRITERS: usize = 8;
+        const MESSAGES_EACH: usize = 25;
+
+        let root = std::env::temp_dir().join(format!("sharpi-store-{}", Uuid::new_v4()));
+        let (id, conversation) = Conversation::new("Busy".to_string());
+        JsonDirStore::new(&root).save_conversation(&id, &conversation).unwrap();
+
+        // Each writer has a store of its own, and so its own lock file
+        // handle, like separate `spi` processes would.
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let (root, id) = (root.clone(), id.clone());
+                std::thread::spawn(move || {
+                    let store = JsonDirStore::new(&root);
+                    for message in 0..MESSAGES_EACH {
+                        let _lock = store.lock_conversation(&id).unwrap();
+                        let mut conversation = store.load_conversation(&id).unwrap();
+                        conversation.add_user_message(format!("{}-{}", writer, message));
+                        store.save_conversation(&id, &conversation).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // Readers take no lock, but must never see a partly written file.
+        let reader = {
+            let (root, id) = (root.clone(), id.clone());
+            std::thread::spawn(move || {
+                let store = JsonDirStore::new(&root);
+                for _ in 0..200 {
+                    store.load_conversation(&id).unwrap();
+                }
+            })
+        };
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        reader.join().unwrap();
+
+        let store = JsonDirStore::new(&root);
+        let messages = store.load_conversation(&id).unwrap().messages;
+        assert_eq!(messages.len(), WRITERS * MESSAGES_EACH);
+        assert_eq!(store.conversation_ids().unwrap(), vec![id]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_saves_keep_the_index_complete() {
+        const WRITERS: usize = 8;
+        const CONVERSATIONS_EACH: usize = 10;
+
+        let root = std::env::temp_dir().join(format!("sharpi-store-{}", Uuid::new_v4()));
+        let store = JsonDirStore::new(&root);
+        let (id, conversation) = Conversation::new("First".to_string());
+        store.save_conversation(&id, &conversation).unwrap();
+        store.list_metadata().unwrap();
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let root = root.clone();
+                std::thread::spawn(move || {
+                    let store = JsonDirStore::new(&root);
+                    for _ in 0..CONVERSATIONS_EACH {
+                        let (id, conversation) = Conversation::new("New".to_string());
+                        store.save_conversation(&id, &conversation).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(store.load_index().len(), 1 + WRITERS * CONVERSATIONS_EACH);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
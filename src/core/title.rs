@@ -0,0 +1,219 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::clients::{ChatMessage, ChatRequest, LlmClient};
+use crate::config::ClientConfig;
+use crate::core::history::{Conversation, History, Message, Role, UsageEntry, DEFAULT_TITLE};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use log::{info, warn};
+
+/// Longest title kept, in characters.
+const TITLE_LENGTH: usize = 60;
+
+/// How much of each message is shown to the model when asking for a title.
+const EXCERPT_LENGTH: usize = 1000;
+
+/// Titles are a handful of words; this leaves room for a few stray ones.
+const TITLE_MAX_TOKENS: u32 = 24;
+
+const TITLE_PROMPT: &str = "Write a title of at most six words for the conversation below. \
+Reply with the title only, without quotes or a trailing full stop.";
+
+/// Whether a conversation still has the placeholder title given to
+/// conversations created implicitly, and has a reply to name it after.
+pub fn needs_title(conversation: &Conversation) -> bool {
+    conversation.title == DEFAULT_TITLE
+        && conversation
+            .messages
+            .iter()
+            .any(|message| message.role == Role::Assistant && !message.content.trim().is_empty())
+}
+
+/// Shortens `text` to a single line of at most `TITLE_LENGTH` characters.
+fn shorten(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= TITLE_LENGTH {
+        return text;
+    }
+    format!("{}…", text.chars().take(TITLE_LENGTH).collect::<String>().trim_end())
+}
+
+/// Names a conversation after its first user message, or `None` if it has
+/// none with any text.
+pub fn heuristic_title(messages: &[Message]) -> Option<String> {
+    messages
+        .iter()
+        .find(|message| message.role == Role::User && !message.content.trim().is_empty())
+        .map(|message| shorten(&message.content))
+}
+
+/// Cleans up a title written by a model, which may ignore instructions and
+/// add quotes, a prefix or more lines.
+fn clean_title(reply: &str) -> Option<String> {
+    let line = reply.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = line.strip_prefix("Title:").unwrap_or(line);
+    let line = line
+        .trim()
+        .trim_matches(|c| matches!(c, '"' | '\'' | '*' | '#' | '`'))
+        .trim_end_matches('.')
+        .trim();
+
+    (!line.is_empty()).then(|| shorten(line))
+}
+
+/// Asks the model for a title in a short request of its own, made from the
+/// start of the first exchange. Also returns the tokens the request used,
+/// for the conversation's usage.
+pub fn generate_title(
+    client: &dyn LlmClient,
+    client_config: &ClientConfig,
+    conversation: &Conversation,
+) -> Result<(String, Option<UsageEntry>)> {
+    let mut excerpt = String::new();
+    for message in conversation.messages.iter().filter(|message| message.role != Role::Tool).take(2) {
+        let content: String = message.content.chars().take(EXCERPT_LENGTH).collect();
+        excerpt.push_str(&format!("{}: {}\n\n", message.role, content));
+    }
+
+    let mut request = ChatRequest::new(client_config, vec![
+        ChatMessage::new(Role::System, TITLE_PROMPT),
+        ChatMessage::new(Role::User, &excerpt),
+    ]);
+    request.max_tokens = TITLE_MAX_TOKENS;
+
+    let response = client.chat(&request).context("Failed to generate a title")?;
+    let usage = response.usage.map(|usage| UsageEntry {
+        usage,
+        model: Some(response.model.clone()),
+        timestamp: Utc::now(),
+    });
+
+    let title = clean_title(&response.content).ok_or_else(|| anyhow!("The model replied with an empty title"))?;
+    Ok((title, usage))
+}
+
+/// Gives conversation `id`, if it still has the placeholder title, a real
+/// one: from the model if `auto_title` is enabled, otherwise (or if that
+/// fails) from its first message. Returns false if the title was left
+/// alone.
+///
+/// Meant to run after the reply is saved, with the conversation unlocked:
+/// the lock is only taken to store the result, so a slow title request
+/// doesn't hold up other commands. `client` should not retry, as the title
+/// is not worth waiting for.
+pub fn auto_title(history: &History, id: &str, client: &dyn LlmClient, client_config: &ClientConfig) -> Result<bool> {
+    let conversation = history.get_conversation(id)?;
+    if !needs_title(&conversation) {
+        return Ok(false);
+    }
+
+    let (generated, usage) = if client_config.auto_title {
+        match generate_title(client, client_config, &conversation) {
+            Ok((title, usage)) => (Some(title), usage),
+            Err(err) => {
+                warn!("{:#}; naming the conversation after its first message", err);
+                (None, None)
+            },
+        }
+    } else {
+        (None, None)
+    };
+    let title = generated.or_else(|| heuristic_title(&conversation.messages));
+
+    let _lock = history.lock_conversation(id)?;
+    let mut conversation = history.get_conversation(id)?;
+    let spent = usage.is_some();
+    if let Some(usage) = usage {
+        conversation.record_usage(usage);
+    }
+
+    // Another process may have named it in the meantime.
+    let titled = match title {
+        Some(title) if needs_title(&conversation) => {
+            info!("Titled conversation \"{}\"", title);
+            conversation.title = title;
+            true
+        },
+        _ => false,
+    };
+
+    if titled || spent {
+        history.save_conversation(id, &conversation)?;
+    }
+    Ok(titled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{ChatResponse, ClientError, ClientResult, Usage};
+    use crate::core::store::MemoryStore;
+    use crate::core::usage;
+    use serde_json::Value;
+
+    #[test]
+    fn test_titles_are_cleaned_and_shortened() {
+        assert_eq!(clean_title("\"Rust Lifetimes Explained.\"\n\nHope that helps!"), Some("Rust Lifetimes Explained".to_string()));
+        assert_eq!(clean_title("Title: **Deploy rollback**"), Some("Deploy rollback".to_string()));
+        assert_eq!(clean_title("  \n\"\""), None);
+
+        let (_, mut conversation) = Conversation::new(DEFAULT_TITLE.to_string());
+        conversation.add_user_message(format!("  How do\nI {}", "really ".repeat(20)));
+        assert!(!needs_title(&conversation));
+        conversation.add_assistant_message("Like this.".to_string());
+        assert!(needs_title(&conversation));
+
+        let title = heuristic_title(&conversation.messages).unwrap();
+        assert!(title.starts_with("How do I really really"));
+        assert!(title.ends_with(" re…"));
+        assert_eq!(title.chars().count(), TITLE_LENGTH + 1);
+    }
+
+    struct Titler(Option<&'static str>);
+
+    impl LlmClient for Titler {
+        fn provider(&self) -> &str {
+            "test"
+        }
+
+        fn chat(&self, _request: &ChatRequest) -> ClientResult<ChatResponse> {
+            match self.0 {
+                Some(title) => Ok(ChatResponse {
+                    content: title.to_string(),
+                    model: "titler".to_string(),
+                    tool_calls: Vec::new(),
+                    usage: Some(Usage::new(30, 4)),
+                    raw: Value::Null,
+                }),
+                None => Err(ClientError::Network("unreachable".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_auto_title_records_usage_and_falls_back() {
+        let config: ClientConfig = toml::from_str("api_url = \"http://localhost\"\nmodel = \"m\"").unwrap();
+        let mut history = History::open(Box::new(MemoryStore::new())).unwrap();
+
+        let (id, mut conversation) = history.create_conversation(DEFAULT_TITLE.to_string()).unwrap();
+        conversation.add_user_message("How do I undo a commit?".to_string());
+        conversation.add_assistant_message("git revert".to_string());
+        history.save_conversation(&id, &conversation).unwrap();
+
+        assert!(auto_title(&history, &id, &Titler(Some("Undoing commits")), &config).unwrap());
+        let titled = history.get_conversation(&id).unwrap();
+        assert_eq!(titled.title, "Undoing commits");
+        assert_eq!(titled.usage.total_tokens, 34);
+        let report = usage::build_report(&[(id.clone(), titled)], |_| None, None, None);
+        assert_eq!(report.by_model[0].label, "titler");
+        assert_eq!(report.total.usage.total_tokens, 34);
+
+        assert!(!auto_title(&history, &id, &Titler(Some("Again")), &config).unwrap());
+
+        let (id, _) = history.create_conversation(DEFAULT_TITLE.to_string()).unwrap();
+        history.save_conversation(&id, &conversation).unwrap();
+        assert!(auto_title(&history, &id, &Titler(None), &config).unwrap());
+        assert_eq!(history.get_conversation(&id).unwrap().title, "How do I undo a commit?");
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright (c) 2025 SharPi Contributors
+// MIT License
+
+use crate::clients::Usage;
+use crate::config::ModelPricing;
+use crate::core::history::Conversation;
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+/// Token usage and spend for one row of a report.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UsageLine {
+    pub label: String,
+    pub usage: Usage,
+    /// Spend on messages whose model has a configured price.
+    pub cost: f64,
+    /// Tokens from messages whose model has no configured price.
+    pub unpriced_tokens: u64,
+}
+
+impl UsageLine {
+    fn add(&mut self, usage: Usage, pricing: Option<ModelPricing>) {
+        self.usage += usage;
+        match pricing {
+            Some(pricing) => self.cost += pricing.cost(&usage),
+            None => self.unpriced_tokens += usage.total_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UsageReport {
+    /// Sorted by cost, most expensive first.
+    pub by_conversation: Vec<UsageLine>,
+    /// Sorted by cost, most expensive first.
+    pub by_model: Vec<UsageLine>,
+    /// Sorted by date, oldest first; labels are `YYYY-MM-DD` (UTC).
+    pub by_day: Vec<UsageLine>,
+    pub total: UsageLine,
+}
+
+/// Aggregates the usage recorded on assistant messages (including replaced
+/// ones), summaries and other requests made for a conversation.
+///
+/// Only messages timestamped within `since..=until` (UTC dates, either end
+/// optional) are counted. Messages without a recorded model are reported
+/// under "unknown".
+pub fn build_report(
+    conversations: &[(String, Conversation)],
+    pricing: impl Fn(&str) -> Option<ModelPricing>,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> UsageReport {
+    let mut by_conversation = Vec::new();
+    let mut by_model: BTreeMap<String, UsageLine> = BTreeMap::new();
+    let mut by_day: BTreeMap<NaiveDate, UsageLine> = BTreeMap::new();
+    let mut total = UsageLine {
+        label: "Total".to_string(),
+        ..UsageLine::default()
+    };
+
+    for (id, conversation) in conversations {
+        let mut line = UsageLine {
+            label: format!("{} ({})", conversation.title, short_id(id)),
+            ..UsageLine::default()
+        };
+
+        let summary = conversation
+            .summary
+            .iter()
+            .map(|summary| (summary.usage, summary.model.as_deref(), summary.created_at));
+        let other = conversation
+            .other_usage
+            .iter()
+            .map(|entry| (Some(entry.usage), entry.model.as_deref(), entry.timestamp));
+        let messages = conversation
+            .all_messages()
+            .into_iter()
+            .map(|message| (message.usage, message.model.as_deref(), message.timestamp));
+
+        for (usage, model, timestamp) in messages.chain(summary).chain(other) {
+            let Some(usage) = usage else {
+                continue;
+            };
+
+            let day = timestamp.date_naive();
+            if since.is_some_and(|since| day < since) || until.is_some_and(|until| day > until) {
+                continue;
+            }
+
+            let model = model.unwrap_or("unknown");
+            let price = pricing(model);
+
+            line.add(usage, price);
+            total.add(usage, price);
+            by_model
+                .entry(model.to_string())
+                .or_insert_with(|| UsageLine { label: model.to_string(), ..UsageLine::default() })
+                .add(usage, price);
+            by_day
+                .entry(day)
+                .or_insert_with(|| UsageLine { label: day.to_string(), ..UsageLine::default() })
+                .add(usage, price);
+        }
+
+        if line.usage.total_tokens > 0 {
+            by_conversation.push(line);
+        }
+    }
+
+    let by_cost = |a: &UsageLine, b: &UsageLine| {
+        b.cost
+            .total_cmp(&a.cost)
+            .then(b.usage.total_tokens.cmp(&a.usage.total_tokens))
+    };
+
+    by_conversation.sort_by(by_cost);
+    let mut by_model: Vec<UsageLine> = by_model.into_values().collect();
+    by_model.sort_by(by_cost);
+
+    UsageReport {
+        by_conversation,
+        by_model,
+        by_day: by_day.into_values().collect(),
+        total,
+    }
+}
+
+fn short_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::ChatResponse;
+    use serde_json::Value;
+
+    fn reply(conversation: &mut Conversation, model: &str, prompt: u64, completion: u64) {
+        conversation.add_assistant_reply(&ChatResponse {
+            content: "ok".to_string(),
+            model: model.to_string(),
+            tool_calls: Vec::new(),
+            usage: Some(Usage::new(prompt, completion)),
+            raw: Value::Null,
+        });
+    }
+
+    #[test]
+    fn test_report_prices_known_models_only() {
+        let (_, mut first) = Conversation::new("First".to_string());
+        reply(&mut first, "gpt-4o", 1_000_000, 1_000_000);
+        let (_, mut second) = Conversation::new("Second".to_string());
+        reply(&mut second, "local", 500, 500);
+
+        let conversations = vec![("aaaaaaaa-1".to_string(), first), ("bbbbbbbb-2".to_string(), second)];
+        let pricing = |model: &str| {
+            (model == "gpt-4o").then_some(ModelPricing { input: 2.5, output: 10.0 })
+        };
+
+        let report = build_report(&conversations, pricing, None, None);
+        assert_eq!(report.total.cost, 12.5);
+        assert_eq!(report.total.unpriced_tokens, 1000);
+        assert_eq!(report.by_model[0].label, "gpt-4o");
+        assert_eq!(report.by_conversation[0].label, "First (aaaaaaaa)");
+        assert_eq!(report.by_day.len(), 1);
+
+        let tomorrow = chrono::Utc::now().date_naive().succ_opt();
+        let report = build_report(&conversations, pricing, tomorrow, None);
+        assert_eq!(report.total.usage.total_tokens, 0);
+        assert!(report.by_conversation.is_empty());
+    }
+}